@@ -0,0 +1,85 @@
+use criterion::{
+    BatchSize, Criterion, Throughput, black_box, criterion_group,
+    criterion_main,
+};
+use rpgmad_lib::{ArchiveEntry, ArchiveReader, Decrypter, Engine};
+use std::io::{Cursor, Read};
+
+const DATA_SIZE: usize = 16 << 20;
+
+/// Size of the buffer, that entry data is read into. Odd, so reads end in the middle of key words.
+const READ_CHUNK_SIZE: usize = 4093;
+
+fn build_archive(data: &[u8], engine: Engine) -> Vec<u8> {
+    let archive_entries = [ArchiveEntry {
+        path: b"Audio\\BGM\\Theme.ogg",
+        data,
+    }];
+
+    let mut archive =
+        vec![0; Decrypter::encrypted_buffer_size(&archive_entries, engine)];
+    Decrypter::new().encrypt(&archive_entries, engine, &mut archive);
+    archive
+}
+
+fn keystream(c: &mut Criterion) {
+    let data = (0..=u8::MAX).cycle().take(DATA_SIZE).collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("keystream");
+    group.throughput(Throughput::Bytes(DATA_SIZE as u64));
+    group.sample_size(20);
+
+    for engine in [Engine::XP, Engine::VXAce] {
+        let archive = build_archive(&data, engine);
+
+        group.bench_function(format!("decrypt/{engine}"), |b| {
+            b.iter_batched_ref(
+                || archive.clone(),
+                |archive| {
+                    let decrypter = Decrypter::new();
+
+                    for entry in decrypter.decrypt(archive).unwrap() {
+                        black_box(entry.unwrap());
+                    }
+                },
+                BatchSize::LargeInput,
+            );
+        });
+
+        group.bench_function(format!("encrypt/{engine}"), |b| {
+            let archive_entries = [ArchiveEntry {
+                path: b"Audio\\BGM\\Theme.ogg",
+                data: &data,
+            }];
+            let mut buffer = vec![0; archive.len()];
+
+            b.iter(|| {
+                Decrypter::new().encrypt(
+                    &archive_entries,
+                    engine,
+                    black_box(&mut buffer),
+                );
+            });
+        });
+
+        group.bench_function(format!("read_unaligned/{engine}"), |b| {
+            let mut chunk = vec![0; READ_CHUNK_SIZE];
+
+            b.iter(|| {
+                let mut reader =
+                    ArchiveReader::new(Cursor::new(&archive)).unwrap();
+                let entry = reader.next_entry().unwrap().unwrap();
+                let mut entry_reader = reader.entry_reader(&entry).unwrap();
+
+                while entry_reader.read(&mut chunk).unwrap() != 0 {
+                    black_box(&chunk);
+                }
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, keystream);
+criterion_main!(benches);
@@ -0,0 +1,307 @@
+use crate::{
+    ArchiveEntry, Decrypter, EncryptOptions, Engine, ExtractError,
+    scan::Scanner,
+};
+use alloc::{borrow::Cow, collections::BTreeMap, vec, vec::Vec};
+use core::fmt;
+
+/// Struct representing a file in [`Archive`].
+///
+/// Same as [`ArchiveEntry`], but `path` and `data` can be either borrowed or owned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveFile<'a> {
+    pub path: Cow<'a, [u8]>,
+    pub data: Cow<'a, [u8]>,
+}
+
+impl<'a> From<ArchiveEntry<'a>> for ArchiveFile<'a> {
+    fn from(entry: ArchiveEntry<'a>) -> Self {
+        Self {
+            path: Cow::Borrowed(entry.path),
+            data: Cow::Borrowed(entry.data),
+        }
+    }
+}
+
+/// An editable set of archive files, that can be serialized back into an encrypted archive.
+///
+/// Files keep the order, in which they were added to the archive, and paths are unique. Paths are compared byte-by-byte, so note that RPG Maker stores paths with backslash separators, e.g. `Data\System.rvdata2`.
+///
+/// Files are looked up by path in logarithmic time, so building archives of thousands of files stays fast.
+#[derive(Clone, Default)]
+pub struct Archive<'a> {
+    files: Vec<ArchiveFile<'a>>,
+    // Index of every file in `files` by its path
+    positions: BTreeMap<Cow<'a, [u8]>, usize>,
+}
+
+impl fmt::Debug for Archive<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Archive")
+            .field("files", &self.files)
+            .finish()
+    }
+}
+
+// Path index is derived from the files, so only the files are compared
+impl PartialEq for Archive<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.files == other.files
+    }
+}
+
+impl Eq for Archive<'_> {}
+
+impl<'a> Archive<'a> {
+    /// Creates a new empty [`Archive`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            positions: BTreeMap::new(),
+        }
+    }
+
+    /// Creates a new [`Archive`] from decrypted entries, e.g. returned from [`Decrypter::decrypt`], without copying their data.
+    ///
+    /// If multiple entries have the same path, the last one wins.
+    pub fn from_entries<I, E>(entries: I) -> Self
+    where
+        I: IntoIterator<Item = E>,
+        E: Into<ArchiveFile<'a>>,
+    {
+        let mut archive = Self::new();
+
+        for file in entries {
+            let file = file.into();
+            archive.insert(file.path, file.data);
+        }
+
+        archive
+    }
+
+    /// Decrypts the archive content into a new [`Archive`], leaving `archive_data` untouched.
+    ///
+    /// Paths and data of all files are copied, so returned [`Archive`] doesn't borrow `archive_data`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`crate::ArchiveIndex::new`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{Archive, Engine};
+    /// use std::fs::{read, write};
+    ///
+    /// let data = read("C:/Game/Game.rgss3a").unwrap();
+    /// let mut archive = Archive::open(&data).unwrap();
+    ///
+    /// let map = read("translation/Map001.rvdata2").unwrap();
+    /// archive.replace(b"Data\\Map001.rvdata2", map);
+    /// archive.remove(b"Data\\Map002.rvdata2");
+    ///
+    /// write("C:/Game/Game.rgss3a", archive.to_bytes(Engine::VXAce)).unwrap();
+    /// ```
+    pub fn open(archive_data: &[u8]) -> Result<Archive<'static>, ExtractError> {
+        let mut scanner = Scanner::new(archive_data)?;
+        let engine = scanner.engine();
+        let mut archive = Archive::new();
+
+        for raw_entry in &mut scanner {
+            let raw_entry = raw_entry?;
+
+            let mut path = archive_data[raw_entry.path.clone()].to_vec();
+            raw_entry.xor_path(engine, &mut path);
+
+            let start = raw_entry.offset as usize;
+            let mut data =
+                archive_data[start..start + raw_entry.size as usize].to_vec();
+            Decrypter::xor_data(raw_entry.key, &mut data);
+
+            archive.insert(path, data);
+        }
+
+        Ok(archive)
+    }
+
+    /// Returns the index of the file with the given `path` in [`Archive::files`].
+    #[inline]
+    pub(crate) fn position(&self, path: &[u8]) -> Option<usize> {
+        self.positions.get(path).copied()
+    }
+
+    /// Returns the count of files in the archive.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Returns `true` if the archive contains no files.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Returns all files of the archive.
+    #[must_use]
+    pub fn files(&self) -> &[ArchiveFile<'a>] {
+        &self.files
+    }
+
+    /// Returns the file with the given `path`.
+    #[must_use]
+    pub fn get(&self, path: &[u8]) -> Option<&ArchiveFile<'a>> {
+        self.position(path).map(|idx| &self.files[idx])
+    }
+
+    /// Returns `true` if the archive contains a file with the given `path`.
+    #[must_use]
+    pub fn contains(&self, path: &[u8]) -> bool {
+        self.position(path).is_some()
+    }
+
+    /// Inserts a file into the archive.
+    ///
+    /// If a file with the same path already exists, its data is replaced in place, and the old file is returned. Otherwise, the file is appended to the end of the archive.
+    pub fn insert(
+        &mut self,
+        path: impl Into<Cow<'a, [u8]>>,
+        data: impl Into<Cow<'a, [u8]>>,
+    ) -> Option<ArchiveFile<'a>> {
+        let file = ArchiveFile {
+            path: path.into(),
+            data: data.into(),
+        };
+
+        if let Some(idx) = self.position(&file.path) {
+            Some(core::mem::replace(&mut self.files[idx], file))
+        } else {
+            self.positions.insert(file.path.clone(), self.files.len());
+            self.files.push(file);
+            None
+        }
+    }
+
+    /// Removes the file with the given `path` from the archive, and returns it.
+    pub fn remove(&mut self, path: &[u8]) -> Option<ArchiveFile<'a>> {
+        let idx = self.positions.remove(path)?;
+
+        // Files after the removed one are shifted back
+        for position in self.positions.values_mut() {
+            if *position > idx {
+                *position -= 1;
+            }
+        }
+
+        Some(self.files.remove(idx))
+    }
+
+    /// Replaces data of the existing file with the given `path`, and returns the old data.
+    ///
+    /// Unlike [`Archive::insert`], does nothing and returns [`None`] if there's no such file.
+    pub fn replace(
+        &mut self,
+        path: &[u8],
+        data: impl Into<Cow<'a, [u8]>>,
+    ) -> Option<Cow<'a, [u8]>> {
+        let idx = self.position(path)?;
+        Some(core::mem::replace(&mut self.files[idx].data, data.into()))
+    }
+
+    /// Changes the path of the file with the given `path` to `new_path`.
+    ///
+    /// # Returns
+    /// - `true` if the file was renamed.
+    /// - `false` if there's no file with the given `path`, or `new_path` is already taken by another file.
+    pub fn rename(
+        &mut self,
+        path: &[u8],
+        new_path: impl Into<Cow<'a, [u8]>>,
+    ) -> bool {
+        let new_path = new_path.into();
+
+        let Some(idx) = self.position(path) else {
+            return false;
+        };
+
+        if self
+            .position(&new_path)
+            .is_some_and(|new_idx| new_idx != idx)
+        {
+            return false;
+        }
+
+        self.positions.remove(path);
+        self.positions.insert(new_path.clone(), idx);
+        self.files[idx].path = new_path;
+        true
+    }
+
+    /// Returns an iterator over files of the archive as [`ArchiveEntry`] values.
+    pub fn entries(&self) -> impl Iterator<Item = ArchiveEntry<'_>> {
+        self.files.iter().map(|file| ArchiveEntry {
+            path: &file.path,
+            data: &file.data,
+        })
+    }
+
+    /// Returns the engine, whose data files the archive contains: `.rxdata` for XP, `.rvdata` for VX and `.rvdata2` for VX Ace.
+    ///
+    /// Useful to pick the engine for [`Archive::to_bytes`], and the matching archive file extension with [`Engine::extension`].
+    ///
+    /// # Returns
+    /// - Engine of the first data file in the archive.
+    /// - [`None`] if the archive contains no data files.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{Archive, Engine};
+    /// use std::fs::{read, write};
+    ///
+    /// let mut archive = Archive::new();
+    /// archive.insert(&b"Data\\System.rvdata"[..], read("Data/System.rvdata").unwrap());
+    ///
+    /// let engine = archive.detect_engine().unwrap_or(Engine::VXAce);
+    /// write(format!("Game.{}", engine.extension()), archive.to_bytes(engine)).unwrap();
+    /// ```
+    #[must_use]
+    pub fn detect_engine(&self) -> Option<Engine> {
+        self.files
+            .iter()
+            .find_map(|file| Engine::from_data_path(&file.path))
+    }
+
+    /// Encrypts the archive files into a new archive buffer.
+    ///
+    /// # Parameters
+    /// - `engine`: Target archive engine.
+    #[must_use]
+    pub fn to_bytes(&self, engine: Engine) -> Vec<u8> {
+        self.to_bytes_with_options(engine, &mut EncryptOptions::default())
+    }
+
+    /// Same as [`Archive::to_bytes`], but encrypts VX Ace archives with the keys, chosen according to `options`.
+    ///
+    /// # Parameters
+    /// - `engine`: Target archive engine.
+    /// - `options`: Encryption keys options.
+    #[must_use]
+    pub fn to_bytes_with_options(
+        &self,
+        engine: Engine,
+        options: &mut EncryptOptions,
+    ) -> Vec<u8> {
+        let entries = self.entries().collect::<Vec<_>>();
+
+        let mut archive_buffer =
+            vec![0; Decrypter::encrypted_buffer_size(&entries, engine)];
+        Decrypter::new().encrypt_with_options(
+            &entries,
+            engine,
+            options,
+            &mut archive_buffer,
+        );
+
+        archive_buffer
+    }
+}
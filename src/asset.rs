@@ -0,0 +1,303 @@
+use core::fmt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Header, that RPG Maker MV/MZ prepends to encrypted assets: `RPGMV` signature, version `0.3.1` and padding.
+pub const ASSET_HEADER: [u8; 16] = *b"RPGMV\0\0\0\0\x03\x01\0\0\0\0\0";
+
+/// Size of the header of encrypted assets in bytes. Only the first [`ASSET_HEADER_SIZE`] bytes of the original file are encrypted as well.
+pub const ASSET_HEADER_SIZE: usize = ASSET_HEADER.len();
+
+/// First 16 bytes of every PNG file: signature, and length and type of `IHDR` chunk, that always comes first.
+const PNG_HEADER: [u8; 16] = [
+    0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D,
+    0x49, 0x48, 0x44, 0x52,
+];
+
+pub const MV_PNG_EXT: &str = "rpgmvp";
+pub const MV_OGG_EXT: &str = "rpgmvo";
+pub const MV_M4A_EXT: &str = "rpgmvm";
+pub const MZ_PNG_EXT: &str = "png_";
+pub const MZ_OGG_EXT: &str = "ogg_";
+pub const MZ_M4A_EXT: &str = "m4a_";
+
+/// Returns the original extension of an encrypted MV/MZ asset, e.g. `png` for `rpgmvp` and `png_`, ignoring ASCII case.
+///
+/// Returns [`None`] if `extension` is not an extension of encrypted asset.
+#[must_use]
+pub fn asset_extension(extension: &str) -> Option<&'static str> {
+    [
+        (MV_PNG_EXT, "png"),
+        (MV_OGG_EXT, "ogg"),
+        (MV_M4A_EXT, "m4a"),
+        (MZ_PNG_EXT, "png"),
+        (MZ_OGG_EXT, "ogg"),
+        (MZ_M4A_EXT, "m4a"),
+    ]
+    .into_iter()
+    .find(|(encrypted, _)| encrypted.eq_ignore_ascii_case(extension))
+    .map(|(_, original)| original)
+}
+
+#[derive(Debug, Error)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum AssetError {
+    #[error(
+        "Invalid encryption key length: {0}. Expected 32 hexadecimal digits."
+    )]
+    InvalidKeyLength(usize),
+    #[error(
+        "Invalid character {0:?} in encryption key. Expected a hexadecimal digit."
+    )]
+    InvalidKeyChar(char),
+    #[error("Invalid asset header: {0:?}. Expected: {ASSET_HEADER:?}")]
+    InvalidHeader([u8; ASSET_HEADER_SIZE]),
+    #[error("Asset has size {size}, but at least {needed} bytes are needed.")]
+    UnexpectedEof { size: usize, needed: usize },
+    #[error(
+        "Buffer has size {actual}, but encrypted asset has size {expected}."
+    )]
+    BufferSizeMismatch { expected: usize, actual: usize },
+}
+
+/// Encryption key of RPG Maker MV/MZ assets.
+///
+/// The game stores it as `encryptionKey` hexadecimal string in `data/System.json`. `Display` implementation formats the key back into that string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct AssetKey([u8; 16]);
+
+impl AssetKey {
+    /// Creates a new [`AssetKey`] from raw key bytes.
+    #[must_use]
+    pub const fn new(key: [u8; 16]) -> Self {
+        Self(key)
+    }
+
+    /// Parses `encryptionKey` value from `System.json`.
+    ///
+    /// # Errors
+    ///
+    /// - [`AssetError::InvalidKeyLength`] if `hex` doesn't consist of exactly 32 characters.
+    /// - [`AssetError::InvalidKeyChar`] if `hex` contains a character, that is not a hexadecimal digit.
+    ///
+    /// # Example
+    /// ```
+    /// use rpgmad_lib::AssetKey;
+    ///
+    /// let key = AssetKey::from_hex("d41d8cd98f00b204e9800998ecf8427e").unwrap();
+    /// assert_eq!(key.as_bytes()[0], 0xD4);
+    /// assert_eq!(key.to_string(), "d41d8cd98f00b204e9800998ecf8427e");
+    /// ```
+    pub fn from_hex(hex: &str) -> Result<Self, AssetError> {
+        let char_count = hex.chars().count();
+
+        if char_count != 32 {
+            return Err(AssetError::InvalidKeyLength(char_count));
+        }
+
+        let mut key = [0; 16];
+        let mut chars = hex.chars();
+
+        for byte in &mut key {
+            for _ in 0..2 {
+                let char = chars.next().unwrap_or_default();
+                let digit = char
+                    .to_digit(16)
+                    .ok_or(AssetError::InvalidKeyChar(char))?;
+
+                *byte = (*byte << 4) | digit as u8;
+            }
+        }
+
+        Ok(Self(key))
+    }
+
+    /// Recovers the key from any encrypted PNG asset (`.rpgmvp` or `.png_`), for games, whose `System.json` is missing or has the key stripped.
+    ///
+    /// Every PNG file starts with the same 16 bytes, so XOR of them with the encrypted bytes gives the key.
+    ///
+    /// # Errors
+    ///
+    /// - [`AssetError::UnexpectedEof`] if `encrypted_png` is shorter than 32 bytes.
+    /// - [`AssetError::InvalidHeader`] if `encrypted_png` doesn't start with [`ASSET_HEADER`].
+    pub fn recover_from_png(encrypted_png: &[u8]) -> Result<Self, AssetError> {
+        let encrypted = read_header(encrypted_png)?;
+
+        if encrypted.len() < PNG_HEADER.len() {
+            return Err(AssetError::UnexpectedEof {
+                size: encrypted_png.len(),
+                needed: ASSET_HEADER_SIZE + PNG_HEADER.len(),
+            });
+        }
+
+        let mut key = PNG_HEADER;
+
+        for (key_byte, encrypted_byte) in key.iter_mut().zip(encrypted) {
+            *key_byte ^= encrypted_byte;
+        }
+
+        Ok(Self(key))
+    }
+
+    /// Returns raw key bytes.
+    #[must_use]
+    pub const fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    #[inline]
+    fn xor(&self, data: &mut [u8]) {
+        for (byte, key_byte) in data.iter_mut().zip(self.0) {
+            *byte ^= key_byte;
+        }
+    }
+}
+
+impl fmt::Display for AssetKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the header of encrypted asset, and returns the data after it.
+fn read_header(asset_data: &[u8]) -> Result<&[u8], AssetError> {
+    if asset_data.len() < ASSET_HEADER_SIZE {
+        return Err(AssetError::UnexpectedEof {
+            size: asset_data.len(),
+            needed: ASSET_HEADER_SIZE,
+        });
+    }
+
+    let (header, data) = asset_data.split_at(ASSET_HEADER_SIZE);
+
+    if header != ASSET_HEADER {
+        let mut invalid_header = [0; ASSET_HEADER_SIZE];
+        invalid_header.copy_from_slice(header);
+        return Err(AssetError::InvalidHeader(invalid_header));
+    }
+
+    Ok(data)
+}
+
+/// A struct responsible for decrypting and encrypting RPG Maker MV/MZ assets: images (`.rpgmvp`, `.png_`) and audio (`.rpgmvo`, `.ogg_`, `.rpgmvm`, `.m4a_`).
+///
+/// Encrypted asset is [`ASSET_HEADER`], followed by the original file, whose first [`ASSET_HEADER_SIZE`] bytes are encrypted with XOR against the [`AssetKey`]. Like [`crate::Decrypter`], it works without allocations, so it's usable in `no_std` environments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssetDecrypter {
+    key: AssetKey,
+}
+
+impl AssetDecrypter {
+    /// Creates a new [`AssetDecrypter`] with the given `key`.
+    #[must_use]
+    pub const fn new(key: AssetKey) -> Self {
+        Self { key }
+    }
+
+    /// Returns the key of the decrypter.
+    #[must_use]
+    pub const fn key(&self) -> AssetKey {
+        self.key
+    }
+
+    /// Decrypts the asset in place.
+    ///
+    /// # Parameters
+    /// - `asset_data`: Content of the encrypted asset.
+    ///
+    /// # Returns
+    /// - Decrypted file data, which is `asset_data` without the header.
+    ///
+    /// # Errors
+    ///
+    /// - [`AssetError::UnexpectedEof`] if `asset_data` is shorter than the header.
+    /// - [`AssetError::InvalidHeader`] if `asset_data` doesn't start with [`ASSET_HEADER`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{AssetDecrypter, AssetKey};
+    /// use std::fs::{read, write};
+    ///
+    /// let key = AssetKey::from_hex("d41d8cd98f00b204e9800998ecf8427e").unwrap();
+    /// let decrypter = AssetDecrypter::new(key);
+    ///
+    /// let mut data = read("img/titles1/Castle.png_").unwrap();
+    /// let png = decrypter.decrypt(&mut data).unwrap();
+    ///
+    /// write("img/titles1/Castle.png", png).unwrap();
+    /// ```
+    pub fn decrypt<'a>(
+        &self,
+        asset_data: &'a mut [u8],
+    ) -> Result<&'a mut [u8], AssetError> {
+        read_header(asset_data)?;
+
+        let data = &mut asset_data[ASSET_HEADER_SIZE..];
+        self.key.xor(data);
+
+        Ok(data)
+    }
+
+    /// Returns the size for the encrypted buffer of the asset in bytes.
+    ///
+    /// # Parameters
+    /// - `data`: File data to encrypt.
+    #[must_use]
+    pub const fn encrypted_buffer_size(data: &[u8]) -> usize {
+        ASSET_HEADER_SIZE + data.len()
+    }
+
+    /// Writes encrypted asset data to `asset_buffer`.
+    ///
+    /// `asset_buffer` must be manually pre-allocated by you with the size that [`AssetDecrypter::encrypted_buffer_size`] returns.
+    ///
+    /// # Parameters
+    /// - `data`: File data to encrypt.
+    /// - `asset_buffer`: Buffer to write encrypted asset into.
+    ///
+    /// # Errors
+    ///
+    /// - [`AssetError::BufferSizeMismatch`] if `asset_buffer` has wrong size.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{AssetDecrypter, AssetKey};
+    /// use std::fs::{read, write};
+    ///
+    /// let key = AssetKey::from_hex("d41d8cd98f00b204e9800998ecf8427e").unwrap();
+    /// let decrypter = AssetDecrypter::new(key);
+    ///
+    /// let data = read("audio/bgm/Theme1.ogg").unwrap();
+    /// let mut asset_buffer = vec![0; AssetDecrypter::encrypted_buffer_size(&data)];
+    /// decrypter.encrypt(&data, &mut asset_buffer).unwrap();
+    ///
+    /// write("audio/bgm/Theme1.ogg_", asset_buffer).unwrap();
+    /// ```
+    pub fn encrypt(
+        &self,
+        data: &[u8],
+        asset_buffer: &mut [u8],
+    ) -> Result<(), AssetError> {
+        let expected = Self::encrypted_buffer_size(data);
+
+        if asset_buffer.len() != expected {
+            return Err(AssetError::BufferSizeMismatch {
+                expected,
+                actual: asset_buffer.len(),
+            });
+        }
+
+        let (header, encrypted) = asset_buffer.split_at_mut(ASSET_HEADER_SIZE);
+        header.copy_from_slice(&ASSET_HEADER);
+        encrypted.copy_from_slice(data);
+        self.key.xor(encrypted);
+
+        Ok(())
+    }
+}
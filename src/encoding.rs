@@ -0,0 +1,85 @@
+use alloc::{borrow::Cow, string::String};
+#[cfg(feature = "encoding")]
+use encoding_rs::SHIFT_JIS;
+
+/// Encoding of archive entry paths.
+///
+/// RPG Maker stores paths in the system encoding of the machine, where the archive was created. For Japanese games, it's usually CP932 (Shift JIS), which requires `encoding` feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PathEncoding {
+    /// UTF-8, with invalid sequences replaced by `U+FFFD` when decoding.
+    #[default]
+    Utf8Lossy,
+    /// Strict UTF-8.
+    Utf8,
+    /// Strict CP932, Microsoft's variant of Shift JIS.
+    #[cfg(feature = "encoding")]
+    Cp932,
+    /// Either UTF-8 or CP932, detected with [`PathEncoding::detect`].
+    ///
+    /// When encoding, CP932 is used for paths representable in it, and UTF-8 is used for all other paths. Note that this doesn't restore the original bytes of a detected UTF-8 path; to round-trip paths losslessly, encode them with the encoding returned from [`PathEncoding::detect`].
+    #[cfg(feature = "encoding")]
+    Auto,
+}
+
+impl PathEncoding {
+    /// Detects the encoding of `path`.
+    ///
+    /// # Returns
+    /// - [`PathEncoding::Utf8`], if `path` is valid UTF-8. ASCII paths are always detected as UTF-8.
+    /// - [`PathEncoding::Cp932`], if `path` is valid CP932.
+    /// - [`None`] otherwise.
+    #[cfg(feature = "encoding")]
+    #[must_use]
+    pub fn detect(path: &[u8]) -> Option<Self> {
+        if core::str::from_utf8(path).is_ok() {
+            Some(Self::Utf8)
+        } else if Self::Cp932.decode(path).is_some() {
+            Some(Self::Cp932)
+        } else {
+            None
+        }
+    }
+
+    /// Decodes `path` with this encoding.
+    ///
+    /// # Returns
+    /// - Decoded path.
+    /// - [`None`], if `path` cannot be decoded with this encoding.
+    #[must_use]
+    pub fn decode(self, path: &[u8]) -> Option<Cow<'_, str>> {
+        match self {
+            Self::Utf8Lossy => Some(String::from_utf8_lossy(path)),
+            Self::Utf8 => core::str::from_utf8(path).ok().map(Cow::Borrowed),
+            #[cfg(feature = "encoding")]
+            Self::Cp932 => SHIFT_JIS
+                .decode_without_bom_handling_and_without_replacement(path),
+            #[cfg(feature = "encoding")]
+            Self::Auto => Self::detect(path)?.decode(path),
+        }
+    }
+
+    /// Encodes `path` with this encoding, e.g. to build [`crate::ArchiveEntry`] for encryption.
+    ///
+    /// # Returns
+    /// - Encoded path.
+    /// - [`None`], if `path` contains characters, that cannot be represented in this encoding.
+    #[must_use]
+    pub fn encode(self, path: &str) -> Option<Cow<'_, [u8]>> {
+        match self {
+            Self::Utf8Lossy | Self::Utf8 => {
+                Some(Cow::Borrowed(path.as_bytes()))
+            }
+            #[cfg(feature = "encoding")]
+            Self::Cp932 => {
+                let (encoded, _, had_errors) = SHIFT_JIS.encode(path);
+                (!had_errors).then_some(encoded)
+            }
+            #[cfg(feature = "encoding")]
+            Self::Auto => Self::Cp932
+                .encode(path)
+                .or(Some(Cow::Borrowed(path.as_bytes()))),
+        }
+    }
+}
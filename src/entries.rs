@@ -0,0 +1,423 @@
+use crate::{
+    ArchiveEntry, DecryptOptions, Decrypter, ExtractError,
+    scan::{
+        MetadataParser, MetadataSource, RawEntry, Scanner, SliceSource,
+        xor_path,
+    },
+};
+#[cfg(feature = "alloc")]
+use alloc::{collections::BTreeMap, vec::Vec};
+#[cfg(not(feature = "alloc"))]
+use core::ops::Range;
+use core::{iter::FusedIterator, mem};
+
+/// Metadata record of an entry, with its position in the archive.
+struct Record {
+    start: usize,
+    end: usize,
+    entry: RawEntry,
+}
+
+/// Iterator over decrypted [`ArchiveEntry`] entries, returned from [`Decrypter::decrypt`].
+///
+/// Archive metadata is validated once, when the iterator is created, so the number of entries is known up front, and the iterator implements [`ExactSizeIterator`] and [`DoubleEndedIterator`]. Paths and data are decrypted only for the entries, that are actually yielded: entries, skipped with [`Iterator::nth`], [`Iterator::skip`] or their counterparts from the back, are left encrypted.
+///
+/// Iterator owns its parsing state, and only borrows the archive data, so entries don't borrow the [`Decrypter`], which can be reused for other archives right away.
+///
+/// If the metadata is corrupted, iterator yields the entries before the corrupted one, followed by a single error. When iterating from the back, the error is yielded first.
+///
+/// Data of VX Ace entries may be stored in any order, as long as it doesn't overlap the metadata or data of other entries. Without `alloc` feature, the data can only be split off the archive in the order of entries, so data, that precedes data of the previous entries, is reported as [`ExtractError::OverlappingEntries`].
+///
+/// Note, that metadata records can only be parsed from the front. With `alloc` feature, positions of the records are recorded, when the metadata is validated, so [`DoubleEndedIterator::next_back`] parses only the last record. Without it, every call to [`DoubleEndedIterator::next_back`] walks the metadata of the remaining entries, and iterating a whole archive backwards is quadratic in the number of entries.
+pub struct DecryptedEntries<'d> {
+    /// Parser state before the first unread record.
+    parser: MetadataParser,
+    archive_len: usize,
+    /// Start of every record in the archive, and the key, that it's decrypted with.
+    #[cfg(feature = "alloc")]
+    records: Vec<(usize, u32)>,
+
+    // Entries are split off both ends of `metadata`, and their data is taken from `entry_data`, so the yielded entries never alias the unread bytes.
+    /// Unread metadata records. For XP/VX archives, they include entry data.
+    metadata: &'d mut [u8],
+    metadata_pos: usize,
+    /// Data of every VX Ace entry, split off the archive in the order of offsets.
+    #[cfg(feature = "alloc")]
+    entry_data: Vec<&'d mut [u8]>,
+    /// Unread data of VX Ace entries.
+    #[cfg(not(feature = "alloc"))]
+    entry_data: &'d mut [u8],
+    #[cfg(not(feature = "alloc"))]
+    entry_data_pos: usize,
+
+    front: usize,
+    back: usize,
+    error: Option<ExtractError>,
+}
+
+impl<'d> DecryptedEntries<'d> {
+    /// Creates the iterator over `archive_data`, parses the archive header, and validates metadata of all entries.
+    pub(crate) fn new(
+        archive_data: &'d mut [u8],
+        options: &DecryptOptions,
+    ) -> Result<Self, ExtractError> {
+        let mut scanner = Scanner::with_options(archive_data, options)?;
+        let parser = scanner.parser();
+        let engine = parser.engine();
+        let metadata_start = scanner.pos();
+
+        let mut len = 0;
+        let mut metadata_end = metadata_start;
+        let mut data_start = archive_data.len();
+        let mut error = None;
+
+        // Data ranges of the entries by their starts, with the entry indices
+        #[cfg(feature = "alloc")]
+        let mut data_ranges = BTreeMap::new();
+        #[cfg(not(feature = "alloc"))]
+        let mut data_range: Option<Range<usize>> = None;
+
+        #[cfg(feature = "alloc")]
+        let mut records = Vec::new();
+        #[cfg(feature = "alloc")]
+        let mut record = (metadata_start, parser.key());
+
+        while let Some(entry) = scanner.next() {
+            let index = len;
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    error = Some(err);
+                    break;
+                }
+            };
+
+            // Scanner has already checked, that entry data lies inside the archive.
+            let (pos, size) = (entry.offset as usize, entry.size as usize);
+            let record_end = scanner.pos();
+
+            // VX Ace data must follow the metadata, so it can be split off at the start of the first data, and must not overlap data of the previous entries.
+            if engine.is_vx_ace() {
+                if record_end > data_start {
+                    error = Some(ExtractError::OverlappingEntries { index });
+                    break;
+                }
+
+                if size != 0 {
+                    // Ranges don't overlap each other, so only the last range, that starts before the end of this one, can overlap it
+                    #[cfg(feature = "alloc")]
+                    let overlaps = data_ranges
+                        .range(..pos + size)
+                        .next_back()
+                        .is_some_and(|(_, &(end, _))| end > pos);
+                    #[cfg(not(feature = "alloc"))]
+                    let overlaps =
+                        data_range.as_ref().is_some_and(|data| pos < data.end);
+
+                    if pos < record_end || overlaps {
+                        error =
+                            Some(ExtractError::OverlappingEntries { index });
+                        break;
+                    }
+
+                    #[cfg(feature = "alloc")]
+                    data_ranges.insert(pos, (pos + size, index));
+                    #[cfg(not(feature = "alloc"))]
+                    {
+                        data_range = Some(
+                            data_range.map_or(pos, |data| data.start)
+                                ..pos + size,
+                        );
+                    }
+
+                    data_start = data_start.min(pos);
+                }
+            }
+
+            #[cfg(feature = "alloc")]
+            {
+                records.push(record);
+                record = (record_end, scanner.parser().key());
+            }
+
+            len += 1;
+            metadata_end = record_end;
+        }
+
+        let archive_len = archive_data.len();
+        let (metadata, entry_data) = archive_data.split_at_mut(data_start);
+        let metadata = &mut metadata[metadata_start..metadata_end];
+
+        #[cfg(feature = "alloc")]
+        let entry_data = {
+            let mut data =
+                (0..len).map(|_| <&mut [u8]>::default()).collect::<Vec<_>>();
+            let mut rest = entry_data;
+            let mut consumed = data_start;
+
+            for (start, (end, index)) in data_ranges {
+                let (_, tail) = rest.split_at_mut(start - consumed);
+                let (slice, tail) = tail.split_at_mut(end - start);
+
+                rest = tail;
+                consumed = end;
+                data[index] = slice;
+            }
+
+            data
+        };
+
+        Ok(Self {
+            parser,
+            archive_len,
+            #[cfg(feature = "alloc")]
+            records,
+
+            metadata,
+            metadata_pos: metadata_start,
+            entry_data,
+            #[cfg(not(feature = "alloc"))]
+            entry_data_pos: data_start,
+
+            front: 0,
+            back: len,
+            error,
+        })
+    }
+
+    /// Parses the record, that starts at `start` of the archive, with `parser`, and advances it past the record.
+    ///
+    /// # Returns
+    /// - [`None`] if the record can't be parsed, which can't happen for the records, that were validated when the iterator was created.
+    #[inline]
+    fn parse_record(
+        &self,
+        parser: &mut MetadataParser,
+        start: usize,
+    ) -> Option<Record> {
+        let mut source = SliceSource::with_base(
+            self.metadata.get(start - self.metadata_pos..)?,
+            start,
+            self.archive_len,
+        );
+        let entry = parser.next_entry(&mut source).ok()??;
+
+        Some(Record {
+            start,
+            end: source.pos() as usize,
+            entry,
+        })
+    }
+
+    /// Splits the record off the front of the unread metadata, without decrypting it.
+    fn take_front(&mut self) -> Option<(Record, &'d mut [u8], &'d mut [u8])> {
+        let mut parser = self.parser;
+        let record = self.parse_record(&mut parser, self.metadata_pos)?;
+        self.parser = parser;
+        self.front += 1;
+
+        let (record_bytes, rest) = mem::take(&mut self.metadata)
+            .split_at_mut(record.end - self.metadata_pos);
+        self.metadata = rest;
+        self.metadata_pos = record.end;
+
+        let (path, data) = self.split_record(record_bytes, &record, false);
+        Some((record, path, data))
+    }
+
+    /// Splits the last unread record off the back of the unread metadata, without decrypting it.
+    fn take_back(&mut self) -> Option<(Record, &'d mut [u8], &'d mut [u8])> {
+        #[cfg(feature = "alloc")]
+        let (start, mut parser) = {
+            let (start, key) = self.records[self.back - 1];
+            (start, self.parser.at(self.back - 1, key))
+        };
+
+        // Without recorded positions, the record is found by walking the remaining ones
+        #[cfg(not(feature = "alloc"))]
+        let (start, mut parser) = {
+            let mut parser = self.parser;
+            let mut start = self.metadata_pos;
+
+            for _ in self.front..self.back - 1 {
+                start = self.parse_record(&mut parser, start)?.end;
+            }
+
+            (start, parser)
+        };
+
+        let record = self.parse_record(&mut parser, start)?;
+        self.back -= 1;
+
+        let (rest, record_bytes) = mem::take(&mut self.metadata)
+            .split_at_mut(start - self.metadata_pos);
+        self.metadata = rest;
+
+        let (path, data) = self.split_record(record_bytes, &record, true);
+        Some((record, path, data))
+    }
+
+    /// Splits path and data of `record` off `record_bytes`, which start at the start of the record.
+    ///
+    /// Data of VX Ace entries is taken from the unread data instead, see [`DecryptedEntries::take_data`].
+    fn split_record(
+        &mut self,
+        record_bytes: &'d mut [u8],
+        record: &Record,
+        from_back: bool,
+    ) -> (&'d mut [u8], &'d mut [u8]) {
+        let entry = &record.entry;
+        let (offset, size) = (entry.offset as usize, entry.size as usize);
+
+        let (_, rest) =
+            record_bytes.split_at_mut(entry.path.start - record.start);
+        let (path, rest) = rest.split_at_mut(entry.path.len());
+
+        // Data of XP/VX entries directly follows their metadata
+        if self.parser.engine().is_older() {
+            let (_, rest) = rest.split_at_mut(offset - entry.path.end);
+            return (path, rest.split_at_mut(size).0);
+        }
+
+        if size == 0 {
+            return (path, &mut []);
+        }
+
+        (path, self.take_data(offset, size, from_back))
+    }
+
+    /// Takes data of the VX Ace entry, whose record was just split off.
+    #[cfg(feature = "alloc")]
+    #[inline]
+    fn take_data(
+        &mut self,
+        _offset: usize,
+        _size: usize,
+        from_back: bool,
+    ) -> &'d mut [u8] {
+        // Record is already split off, so `front` and `back` point past it
+        let index = if from_back { self.back } else { self.front - 1 };
+        mem::take(&mut self.entry_data[index])
+    }
+
+    /// Splits data of the VX Ace entry, whose record was just split off, off the unread data, off its back if `from_back` is set.
+    #[cfg(not(feature = "alloc"))]
+    fn take_data(
+        &mut self,
+        offset: usize,
+        size: usize,
+        from_back: bool,
+    ) -> &'d mut [u8] {
+        let data_offset = offset - self.entry_data_pos;
+        let entry_data = mem::take(&mut self.entry_data);
+
+        if from_back {
+            let (rest, data) = entry_data.split_at_mut(data_offset);
+            self.entry_data = rest;
+            data.split_at_mut(size).0
+        } else {
+            let (_, rest) = entry_data.split_at_mut(data_offset);
+            let (data, rest) = rest.split_at_mut(size);
+            self.entry_data = rest;
+            self.entry_data_pos = offset + size;
+            data
+        }
+    }
+
+    #[inline]
+    fn decrypt_entry(
+        &self,
+        record: &Record,
+        path: &'d mut [u8],
+        data: &'d mut [u8],
+    ) -> ArchiveEntry<'d> {
+        xor_path(self.parser.engine(), record.entry.path_key, path);
+        Decrypter::xor_data(record.entry.key, data);
+        ArchiveEntry { path, data }
+    }
+}
+
+impl<'d> Iterator for DecryptedEntries<'d> {
+    type Item = Result<ArchiveEntry<'d>, ExtractError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return self.error.take().map(Err);
+        }
+
+        let (record, path, data) = self.take_front()?;
+        Some(Ok(self.decrypt_entry(&record, path, data)))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        for _ in 0..n {
+            if self.front < self.back {
+                self.take_front()?;
+            } else {
+                self.error.take()?;
+            }
+        }
+
+        self.next()
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.len();
+        (len, Some(len))
+    }
+
+    #[inline]
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    #[inline]
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl DoubleEndedIterator for DecryptedEntries<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
+
+        if self.front == self.back {
+            return None;
+        }
+
+        let (record, path, data) = self.take_back()?;
+        Some(Ok(self.decrypt_entry(&record, path, data)))
+    }
+
+    fn nth_back(&mut self, mut n: usize) -> Option<Self::Item> {
+        if n == 0 {
+            return self.next_back();
+        }
+
+        if self.error.take().is_some() {
+            n -= 1;
+        }
+
+        if n >= self.back - self.front {
+            self.metadata = &mut [];
+            self.back = self.front;
+            return None;
+        }
+
+        // Entries after the yielded one are dropped at once, without walking the metadata for each of them.
+        self.back -= n;
+        self.next_back()
+    }
+}
+
+impl ExactSizeIterator for DecryptedEntries<'_> {
+    #[inline]
+    fn len(&self) -> usize {
+        self.back - self.front + usize::from(self.error.is_some())
+    }
+}
+
+impl FusedIterator for DecryptedEntries<'_> {}
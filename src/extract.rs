@@ -0,0 +1,219 @@
+use crate::{ArchiveIndex, EntryInfo, ExtractError, PathEncoding};
+use std::{
+    fs::{OpenOptions, create_dir_all},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+use thiserror::Error;
+
+/// Options for [`ArchiveIndex::extract_to`].
+///
+/// # Fields
+/// - `encoding` - Encoding to decode entry paths with. Defaults to [`PathEncoding::Utf8Lossy`].
+/// - `overwrite` - Whether to overwrite existing files. Defaults to `true`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtractOptions {
+    pub encoding: PathEncoding,
+    pub overwrite: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            encoding: PathEncoding::default(),
+            overwrite: true,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ExtractFileError {
+    #[error("Path cannot be decoded with {0:?} encoding.")]
+    InvalidEncoding(PathEncoding),
+    #[error("Path is empty.")]
+    EmptyPath,
+    #[error("Path {0} is absolute.")]
+    AbsolutePath(String),
+    #[error("Path {0} points outside of the output directory.")]
+    PathTraversal(String),
+    #[error(
+        "Path {0} contains a component, that is not a valid Windows file name."
+    )]
+    InvalidComponent(String),
+    #[error("File {0} already exists.")]
+    AlreadyExists(PathBuf),
+    #[error(transparent)]
+    Extract(#[from] ExtractError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Result of extracting a single archive entry.
+///
+/// # Fields
+/// - `path` - Path of the entry, as stored in the archive.
+/// - `result` - Path of the written file, or an error, if the entry wasn't extracted.
+#[derive(Debug)]
+pub struct ExtractedFile {
+    pub path: Vec<u8>,
+    pub result: Result<PathBuf, ExtractFileError>,
+}
+
+/// Device names, that Windows reserves in every directory, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6",
+    "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6",
+    "LPT7", "LPT8", "LPT9",
+];
+
+/// Returns `true` if `component` is a plain file name on every platform, so pushing it to a [`PathBuf`] can't replace or escape the accumulated path.
+fn is_valid_component(component: &str) -> bool {
+    if component.contains(':') || component.ends_with(['.', ' ']) {
+        return false;
+    }
+
+    let mut components = Path::new(component).components();
+
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        return false;
+    }
+
+    let stem = component
+        .split_once('.')
+        .map_or(component, |(stem, _)| stem)
+        .trim_end_matches(' ');
+
+    !RESERVED_NAMES
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(stem))
+}
+
+/// Converts archive entry `path` to a relative path, that is safe to join to the output directory.
+///
+/// Windows backslash separators are converted to the platform ones, and `.` components are skipped.
+///
+/// # Errors
+///
+/// - [`ExtractFileError::InvalidEncoding`] if `path` cannot be decoded with `encoding`.
+/// - [`ExtractFileError::EmptyPath`] if `path` has no components.
+/// - [`ExtractFileError::AbsolutePath`] if `path` is absolute, or starts with a drive letter, e.g. `C:`.
+/// - [`ExtractFileError::PathTraversal`] if `path` contains `..` components.
+/// - [`ExtractFileError::InvalidComponent`] if a component of `path` contains `:`, which is a drive prefix or an NTFS alternate data stream on Windows, ends with a dot or a space, or is a reserved device name, like `CON` or `NUL.txt`.
+pub fn sanitize_entry_path(
+    path: &[u8],
+    encoding: PathEncoding,
+) -> Result<PathBuf, ExtractFileError> {
+    let decoded = encoding
+        .decode(path)
+        .ok_or(ExtractFileError::InvalidEncoding(encoding))?;
+    let normalized = decoded.replace('\\', "/");
+
+    let bytes = normalized.as_bytes();
+    let has_drive_letter =
+        bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':';
+
+    if normalized.starts_with('/') || has_drive_letter {
+        return Err(ExtractFileError::AbsolutePath(normalized));
+    }
+
+    let mut sanitized = PathBuf::new();
+
+    for component in normalized.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(ExtractFileError::PathTraversal(normalized)),
+            component if !is_valid_component(component) => {
+                return Err(ExtractFileError::InvalidComponent(normalized));
+            }
+            component => sanitized.push(component),
+        }
+    }
+
+    if sanitized.as_os_str().is_empty() {
+        return Err(ExtractFileError::EmptyPath);
+    }
+
+    Ok(sanitized)
+}
+
+impl ArchiveIndex<'_> {
+    fn extract_entry(
+        &self,
+        entry: &EntryInfo,
+        dir: &Path,
+        options: &ExtractOptions,
+    ) -> Result<PathBuf, ExtractFileError> {
+        let output_path =
+            dir.join(sanitize_entry_path(&entry.path, options.encoding)?);
+
+        if let Some(parent) = output_path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let data = self.decrypt_entry(entry)?;
+
+        let mut open_options = OpenOptions::new();
+        open_options.write(true);
+
+        if options.overwrite {
+            open_options.create(true).truncate(true);
+        } else {
+            open_options.create_new(true);
+        }
+
+        let mut file = match open_options.open(&output_path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(ExtractFileError::AlreadyExists(output_path));
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        file.write_all(&data)?;
+        Ok(output_path)
+    }
+
+    /// Decrypts all archive entries and writes them to `dir`.
+    ///
+    /// Entry paths are sanitized with [`sanitize_entry_path`], so entries with absolute paths or paths pointing outside of `dir` are never written. Failure to extract one entry doesn't stop the extraction of the others.
+    ///
+    /// # Parameters
+    /// - `dir`: Output directory. Created, if it doesn't exist.
+    /// - `options`: Extraction options.
+    ///
+    /// # Returns
+    /// - [`ExtractedFile`] for every archive entry, in the archive order.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{ArchiveIndex, ExtractOptions};
+    /// use std::fs::read;
+    ///
+    /// let data = read("C:/Game/Game.rgss3a").unwrap();
+    /// let index = ArchiveIndex::new(&data).unwrap();
+    ///
+    /// for file in index.extract_to("C:/Game", &ExtractOptions::default()) {
+    ///     if let Err(err) = file.result {
+    ///         eprintln!("{}: {err}", String::from_utf8_lossy(&file.path));
+    ///     }
+    /// }
+    /// ```
+    pub fn extract_to(
+        &self,
+        dir: impl AsRef<Path>,
+        options: &ExtractOptions,
+    ) -> Vec<ExtractedFile> {
+        let dir = dir.as_ref();
+
+        self.entries()
+            .iter()
+            .map(|entry| ExtractedFile {
+                path: entry.path.clone(),
+                result: self.extract_entry(entry, dir, options),
+            })
+            .collect()
+    }
+}
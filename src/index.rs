@@ -0,0 +1,159 @@
+use crate::{
+    DecryptOptions, Decrypter, Engine, EntryInfo, ExtractError,
+    scan::Scanner,
+    view::{decrypt_data_into, entry_data},
+};
+use alloc::vec::Vec;
+
+/// Random-access index of the archive entries.
+///
+/// Index walks only the archive metadata, and doesn't modify or decrypt the entry data, so it's cheap to build even for very large archives. Data of the specific entries can be decrypted later with [`ArchiveIndex::decrypt_entry`].
+pub struct ArchiveIndex<'a> {
+    data: &'a [u8],
+    engine: Engine,
+    entries: Vec<EntryInfo>,
+}
+
+impl<'a> ArchiveIndex<'a> {
+    /// Builds the [`ArchiveIndex`] from archive content.
+    ///
+    /// # Parameters
+    /// - `archive_data`: The content of the archive file. Unlike [`Decrypter::decrypt`], this data is not modified.
+    ///
+    /// # Errors
+    ///
+    /// - [`ExtractError::InvalidHeader`] for invalid header.
+    /// - [`ExtractError::InvalidEngine`] for invalid header engine type byte.
+    /// - [`ExtractError::UnexpectedEof`] if archive header or entry metadata is truncated.
+    /// - [`ExtractError::PathTooLong`] if entry path exceeds the remaining archive data.
+    /// - [`ExtractError::EntryOutOfBounds`] if entry data lies outside of the archive.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::ArchiveIndex;
+    /// use std::fs::read;
+    ///
+    /// let data = read("C:/Game/Game.rgss3a").unwrap();
+    /// let index = ArchiveIndex::new(&data).unwrap();
+    ///
+    /// for entry in index.entries() {
+    ///     println!("{} ({} bytes)", String::from_utf8_lossy(&entry.path), entry.size);
+    /// }
+    ///
+    /// if let Some(entry) = index.get(b"Data\\System.rvdata2") {
+    ///     let system = index.decrypt_entry(entry).unwrap();
+    /// }
+    /// ```
+    pub fn new(archive_data: &'a [u8]) -> Result<Self, ExtractError> {
+        Self::with_options(archive_data, &DecryptOptions::default())
+    }
+
+    /// Same as [`ArchiveIndex::new`], but derives keys according to `options`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`ArchiveIndex::new`].
+    pub fn with_options(
+        archive_data: &'a [u8],
+        options: &DecryptOptions,
+    ) -> Result<Self, ExtractError> {
+        let mut scanner = Scanner::with_options(archive_data, options)?;
+        let mut engine = scanner.engine();
+        let mut entries = Vec::new();
+
+        for raw_entry in &mut scanner {
+            let raw_entry = raw_entry?;
+
+            let mut path = archive_data[raw_entry.path.clone()].to_vec();
+            raw_entry.xor_path(engine, &mut path);
+
+            entries.push(EntryInfo {
+                path,
+                offset: raw_entry.offset,
+                size: raw_entry.size,
+                key: raw_entry.key,
+            });
+        }
+
+        if let Some(older_engine) = entries
+            .iter()
+            .find_map(|entry| engine.detect_older(&entry.path))
+        {
+            engine = older_engine;
+        }
+
+        Ok(Self {
+            data: archive_data,
+            engine,
+            entries,
+        })
+    }
+
+    /// Returns the engine of the archive. XP and VX archives are told apart by the extensions of data files among the entries, see [`Engine`].
+    #[must_use]
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Returns metadata of all archive entries, in the order they're stored in the archive.
+    #[must_use]
+    pub fn entries(&self) -> &[EntryInfo] {
+        &self.entries
+    }
+
+    /// Returns metadata of the entry with the given `path`.
+    ///
+    /// Paths are compared byte-by-byte. Note, that RPG Maker stores paths with backslash separators, e.g. `Data\System.rvdata2`.
+    #[must_use]
+    pub fn get(&self, path: &[u8]) -> Option<&EntryInfo> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    /// Decrypts data of `entry` into a new buffer, leaving the archive untouched.
+    ///
+    /// # Parameters
+    /// - `entry`: Entry, returned from this index.
+    ///
+    /// # Errors
+    ///
+    /// - [`ExtractError::EntryOutOfBounds`] if `entry` doesn't belong to this archive and its data lies outside of it. In that case, `index` of the error is the count of entries in this index.
+    pub fn decrypt_entry(
+        &self,
+        entry: &EntryInfo,
+    ) -> Result<Vec<u8>, ExtractError> {
+        let mut decrypted = entry_data(
+            self.data,
+            self.entries.len(),
+            entry.offset,
+            entry.size,
+        )?
+        .to_vec();
+
+        Decrypter::xor_data(entry.key, &mut decrypted);
+        Ok(decrypted)
+    }
+
+    /// Same as [`ArchiveIndex::decrypt_entry`], but decrypts data of `entry` into the beginning of `out`, instead of allocating a new buffer.
+    ///
+    /// # Returns
+    /// - Prefix of `out`, that holds the decrypted data.
+    ///
+    /// # Errors
+    ///
+    /// - Same as [`ArchiveIndex::decrypt_entry`].
+    /// - [`ExtractError::BufferTooSmall`] if `out` is shorter than the data.
+    pub fn decrypt_entry_into<'o>(
+        &self,
+        entry: &EntryInfo,
+        out: &'o mut [u8],
+    ) -> Result<&'o mut [u8], ExtractError> {
+        decrypt_data_into(
+            self.data,
+            self.entries.len(),
+            entry.offset,
+            entry.size,
+            entry.key,
+            out,
+        )
+    }
+}
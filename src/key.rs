@@ -0,0 +1,297 @@
+use crate::{
+    ARCHIVE_HEADER, Engine, ExtractError, next_key_older, next_key_vxace,
+    scan::{RawEntry, Scanner},
+};
+
+/// Longest path size, that is tried when recovering the key. Windows paths don't exceed it.
+const MAX_PATH_SIZE: u32 = 1024;
+
+/// Length of the longest data file extension with the leading dot: `.rvdata2`.
+pub(crate) const DATA_EXT_LEN: usize = 8;
+
+/// Ruby Marshal format version, that every data file starts with.
+const MARSHAL_HEADER: [u8; 2] = [0x04, 0x08];
+
+#[inline]
+fn identity(key: u32) -> u32 {
+    key
+}
+
+#[inline]
+fn xor_entry_key(entry_key: u32, metadata_key: u32) -> u32 {
+    entry_key ^ metadata_key
+}
+
+/// Options, that control how archive keys are derived.
+///
+/// Default options decrypt archives, produced by the stock RPG Maker. Some games ship with modified RGSS libraries, that use a different seed, or derive keys differently.
+///
+/// # Fields
+/// - `base_key` - Base key to use instead of the one stored in the archive. For VX Ace archives, it replaces the key stored after the header. For XP/VX archives, it replaces the fixed `0xDEADCAFE` key. Defaults to [`None`].
+/// - `derive_key` - Derives VX Ace metadata key from the base key. Defaults to `key * 9 + 3`.
+/// - `entry_key` - Derives VX Ace entry data key from the raw key stored in entry metadata and the metadata key. Defaults to `entry_key ^ metadata_key`.
+#[derive(Debug, Clone, Copy)]
+pub struct DecryptOptions {
+    pub base_key: Option<u32>,
+    pub derive_key: fn(u32) -> u32,
+    pub entry_key: fn(u32, u32) -> u32,
+}
+
+impl Default for DecryptOptions {
+    fn default() -> Self {
+        Self {
+            base_key: None,
+            derive_key: next_key_vxace,
+            entry_key: xor_entry_key,
+        }
+    }
+}
+
+/// Decrypts the entry path without allocations, and checks that it looks like a real path.
+///
+/// # Returns
+/// - Decrypted end of the path, which is enough to check the data file extension.
+/// - [`None`] if the path is empty or contains control characters.
+fn decrypt_path_end(
+    archive_data: &[u8],
+    engine: Engine,
+    entry: &RawEntry,
+) -> Option<([u8; DATA_EXT_LEN], usize)> {
+    let path_data = &archive_data[entry.path.clone()];
+
+    if path_data.is_empty() {
+        return None;
+    }
+
+    let mut key = entry.path_key;
+    let key_bytes = key.to_le_bytes();
+    let mut path_end = [0; DATA_EXT_LEN];
+
+    for (idx, byte) in path_data.iter().enumerate() {
+        let decrypted = if engine.is_vx_ace() {
+            byte ^ key_bytes[idx % 4]
+        } else {
+            let decrypted = byte ^ key as u8;
+            key = next_key_older(key);
+            decrypted
+        };
+
+        if decrypted < 0x20 || decrypted == 0x7F {
+            return None;
+        }
+
+        path_end.copy_within(1.., 0);
+        path_end[DATA_EXT_LEN - 1] = decrypted;
+    }
+
+    let len = path_data.len().min(DATA_EXT_LEN);
+    path_end.copy_within(DATA_EXT_LEN - len.., 0);
+
+    Some((path_end, len))
+}
+
+/// Checks whether the archive is decrypted with `options` into plausible entries.
+///
+/// # Returns
+/// - Count of data files, that start with Marshal header.
+/// - [`None`] if the archive has no entries, some entry is out of bounds or has an invalid path, or some data file doesn't start with Marshal header.
+fn verify_candidate(
+    archive_data: &[u8],
+    options: &DecryptOptions,
+) -> Option<usize> {
+    let mut scanner = Scanner::with_options(archive_data, options).ok()?;
+    let engine = scanner.engine();
+
+    let mut entry_count = 0;
+    let mut marshal_count = 0;
+
+    for entry in &mut scanner {
+        let entry = entry.ok()?;
+        entry_count += 1;
+
+        let (path_end, len) = decrypt_path_end(archive_data, engine, &entry)?;
+
+        if Engine::from_data_path(&path_end[..len]).is_none() {
+            continue;
+        }
+
+        let start = entry.offset as usize;
+        let data = &archive_data[start..start + entry.size as usize];
+
+        if data.len() < MARSHAL_HEADER.len() {
+            return None;
+        }
+
+        let key_bytes = entry.key.to_le_bytes();
+
+        if data[0] ^ key_bytes[0] != MARSHAL_HEADER[0]
+            || data[1] ^ key_bytes[1] != MARSHAL_HEADER[1]
+        {
+            return None;
+        }
+
+        marshal_count += 1;
+    }
+
+    (entry_count != 0).then_some(marshal_count)
+}
+
+impl DecryptOptions {
+    /// Recovers the key of archive, that was encrypted with a non-standard seed, using known plaintext.
+    ///
+    /// The size of the first entry path is brute-forced, which gives a candidate metadata key for every guess. The candidate is accepted if the whole archive metadata is decrypted into in-bounds entries with plausible paths, and all data files (`.rxdata`, `.rvdata` and `.rvdata2`, e.g. `Data\Scripts.rvdata2`) start with Ruby Marshal header `\x04\x08`. If multiple candidates are accepted, the one that verifies the most data files wins.
+    ///
+    /// Only the seed is recovered: VX Ace entry data keys are expected to be derived with the default [`DecryptOptions::entry_key`].
+    ///
+    /// # Parameters
+    /// - `archive_data`: The content of the archive file.
+    ///
+    /// # Returns
+    /// - Options with the recovered key in `base_key`. For VX Ace archives, it's the already derived metadata key, so `derive_key` is identity.
+    ///
+    /// # Errors
+    ///
+    /// - [`ExtractError::InvalidHeader`] for invalid header.
+    /// - [`ExtractError::InvalidEngine`] for invalid header engine type byte.
+    /// - [`ExtractError::UnexpectedEof`] if archive is too short to contain the first entry metadata.
+    /// - [`ExtractError::KeyNotFound`] if no candidate key is accepted.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{ArchiveIndex, DecryptOptions};
+    /// use std::fs::read;
+    ///
+    /// let data = read("C:/Game/Game.rgss3a").unwrap();
+    ///
+    /// let index = ArchiveIndex::new(&data).or_else(|_| {
+    ///     let options = DecryptOptions::recover(&data)?;
+    ///     ArchiveIndex::with_options(&data, &options)
+    /// }).unwrap();
+    /// ```
+    pub fn recover(archive_data: &[u8]) -> Result<Self, ExtractError> {
+        // Validates header
+        let engine = Scanner::new(archive_data)?.engine();
+        let header_len = ARCHIVE_HEADER.len() + 1;
+
+        // Offset of the first entry path size: after base key, offset, size and key for VX Ace
+        let path_size_offset = if engine.is_vx_ace() {
+            header_len + 16
+        } else {
+            header_len
+        };
+
+        let Some(path_size_bytes) =
+            archive_data.get(path_size_offset..path_size_offset + 4)
+        else {
+            return Err(ExtractError::UnexpectedEof {
+                offset: archive_data.len() as u64,
+                needed: path_size_offset + 4 - archive_data.len(),
+            });
+        };
+
+        let encrypted_path_size = u32::from_le_bytes([
+            path_size_bytes[0],
+            path_size_bytes[1],
+            path_size_bytes[2],
+            path_size_bytes[3],
+        ]);
+
+        let mut best: Option<(Self, usize)> = None;
+
+        for path_size in 1..=MAX_PATH_SIZE {
+            let options = Self {
+                base_key: Some(encrypted_path_size ^ path_size),
+                derive_key: identity,
+                ..Self::default()
+            };
+
+            if let Some(marshal_count) =
+                verify_candidate(archive_data, &options)
+            {
+                match best {
+                    Some((_, best_count)) if best_count >= marshal_count => {}
+                    _ => best = Some((options, marshal_count)),
+                }
+            }
+        }
+
+        best.map(|(options, _)| options)
+            .ok_or(ExtractError::KeyNotFound)
+    }
+}
+
+/// Policy of choosing data keys of VX Ace archive entries.
+///
+/// # Variants
+/// - `MetadataKey` - Every entry uses the metadata key, derived from the base key. Stored entry keys are all zeros. This is the default.
+/// - `Fixed` - Every entry uses the given key.
+/// - `Derived` - Every entry uses the key, derived from the key of the previous entry with `key * 9 + 3`, starting from the metadata key.
+/// - `Rng` - Every entry uses a key, returned from the caller-supplied random number generator.
+pub enum EntryKeys<'a> {
+    MetadataKey,
+    Fixed(u32),
+    Derived,
+    Rng(&'a mut dyn FnMut() -> u32),
+}
+
+/// Options, that control which keys are used to encrypt archives.
+///
+/// Only VX Ace archives store keys, so XP/VX archives are always encrypted the same way, and options are ignored.
+///
+/// Default options produce deterministic archives with zero base key, which is useful for reproducible builds. To produce archives, that look like the ones produced by RPG Maker, use [`EncryptOptions::random`].
+///
+/// # Fields
+/// - `base_key` - Base key, that is stored after the header, and metadata key is derived from. Defaults to `0`.
+/// - `entry_keys` - Policy of choosing entry data keys. Defaults to [`EntryKeys::MetadataKey`].
+pub struct EncryptOptions<'a> {
+    pub base_key: u32,
+    pub entry_keys: EntryKeys<'a>,
+}
+
+impl Default for EncryptOptions<'_> {
+    fn default() -> Self {
+        Self {
+            base_key: 0,
+            entry_keys: EntryKeys::MetadataKey,
+        }
+    }
+}
+
+impl<'a> EncryptOptions<'a> {
+    /// Creates options with random base key and random entry keys.
+    ///
+    /// # Parameters
+    /// - `rng`: Random number generator, e.g. `rand::random::<u32>`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{Archive, EncryptOptions, Engine};
+    ///
+    /// let archive = Archive::new();
+    /// let mut rng = || 4; // Chosen by fair dice roll.
+    ///
+    /// let mut options = EncryptOptions::random(&mut rng);
+    /// let archive_buffer = archive.to_bytes_with_options(Engine::VXAce, &mut options);
+    /// ```
+    pub fn random(rng: &'a mut dyn FnMut() -> u32) -> Self {
+        Self {
+            base_key: rng(),
+            entry_keys: EntryKeys::Rng(rng),
+        }
+    }
+
+    /// Returns data key of the next entry.
+    #[inline]
+    pub(crate) fn next_entry_key(
+        &mut self,
+        metadata_key: u32,
+        previous_key: u32,
+    ) -> u32 {
+        match &mut self.entry_keys {
+            EntryKeys::MetadataKey => metadata_key,
+            EntryKeys::Fixed(key) => *key,
+            EntryKeys::Derived => next_key_vxace(previous_key),
+            EntryKeys::Rng(rng) => rng(),
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::next_key_older;
+
+/// Count of key words, that are processed at once. Each lane advances independently, so the compiler can vectorize XOR of the whole chunk.
+const LANES: usize = 4;
+
+/// Size of the chunk, that is processed at once, in bytes.
+const CHUNK_SIZE: usize = LANES * 4;
+
+/// Multiplier and increment, that advance the key by [`LANES`] steps at once: applying `key * 7 + 3` four times gives `key * 2401 + 1200`.
+const LANE_MULTIPLIER: u32 = 2401;
+const LANE_INCREMENT: u32 = 1200;
+
+/// Multiplier and increment of the key LCG, that advances the key every four bytes.
+const MULTIPLIER: u32 = 7;
+const INCREMENT: u32 = 3;
+
+/// Advances `key` by `steps` steps of `key * 7 + 3` at once, in `O(log steps)`.
+///
+/// Applying the LCG twice gives another LCG with squared multiplier, so the jump is composed from the powers of two steps, like in exponentiation by squaring.
+#[inline]
+pub(crate) const fn jump_key(key: u32, mut steps: u64) -> u32 {
+    let mut multiplier = MULTIPLIER;
+    let mut increment = INCREMENT;
+
+    let mut total_multiplier: u32 = 1;
+    let mut total_increment: u32 = 0;
+
+    while steps != 0 {
+        if steps & 1 != 0 {
+            total_multiplier = total_multiplier.wrapping_mul(multiplier);
+            total_increment = total_increment
+                .wrapping_mul(multiplier)
+                .wrapping_add(increment);
+        }
+
+        increment = increment.wrapping_mul(multiplier.wrapping_add(1));
+        multiplier = multiplier.wrapping_mul(multiplier);
+        steps >>= 1;
+    }
+
+    key.wrapping_mul(total_multiplier)
+        .wrapping_add(total_increment)
+}
+
+/// Keystream, that entry data is encrypted with. Every four bytes, key advances with `key * 7 + 3`.
+///
+/// Unlike decrypting entry data from its first byte, keystream can be moved to any byte offset of the entry with [`KeyStream::seek`] in `O(log offset)`, so an arbitrary byte range of the entry can be decrypted on its own.
+///
+/// # Example
+/// ```
+/// use rpgmad_lib::KeyStream;
+///
+/// let mut data = *b"Hello, archive!";
+///
+/// let mut keystream = KeyStream::new(0x1234_5678);
+/// keystream.apply_keystream(&mut data);
+///
+/// // Decrypt only the end of the data, which starts in the middle of a key word.
+/// let mut tail = data[7..].to_vec();
+/// keystream.seek(7);
+/// keystream.apply_keystream(&mut tail);
+///
+/// assert_eq!(tail, b"archive!");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyStream {
+    initial_key: u32,
+    key: u32,
+    key_byte_pos: usize,
+    pos: u64,
+}
+
+impl KeyStream {
+    /// Creates a new [`KeyStream`] at the first byte of the entry, encrypted with `key`.
+    #[must_use]
+    pub const fn new(key: u32) -> Self {
+        Self {
+            initial_key: key,
+            key,
+            key_byte_pos: 0,
+            pos: 0,
+        }
+    }
+
+    /// Moves the keystream to `byte_offset` from the start of the entry.
+    pub fn seek(&mut self, byte_offset: u64) {
+        self.key = jump_key(self.initial_key, byte_offset / 4);
+        self.key_byte_pos = (byte_offset % 4) as usize;
+        self.pos = byte_offset;
+    }
+
+    /// Returns the current byte offset of the keystream from the start of the entry.
+    #[must_use]
+    pub const fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Decrypts `data` if it's encrypted, encrypts `data` if it's decrypted, and advances the keystream past its end.
+    ///
+    /// `data` must start at [`KeyStream::position`] of the entry.
+    pub fn apply_keystream(&mut self, data: &mut [u8]) {
+        xor_keystream(&mut self.key, &mut self.key_byte_pos, data);
+        self.pos += data.len() as u64;
+    }
+}
+
+#[inline]
+fn xor_word(word: &mut [u8], key: u32) {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(word);
+    word.copy_from_slice(&(u32::from_le_bytes(bytes) ^ key).to_le_bytes());
+}
+
+/// XORs `data` with the keystream, that starts at byte `key_byte_pos` of `key`. Every four bytes, key advances with `key * 7 + 3`.
+///
+/// The partially used key word is finished byte by byte, then whole [`CHUNK_SIZE`] chunks are processed word by word with [`LANES`] independent keys, and the rest is processed byte by byte.
+///
+/// After the call, `key` and `key_byte_pos` hold the keystream state past the end of `data`: `key_byte_pos` of `4` means that `key` is used up, and must be advanced before the next byte.
+#[inline]
+pub(crate) fn xor_keystream(
+    key: &mut u32,
+    key_byte_pos: &mut usize,
+    data: &mut [u8],
+) {
+    let mut data = data;
+
+    // Finish the partially used key word
+    while *key_byte_pos % 4 != 0 {
+        let Some((byte, rest)) = data.split_first_mut() else {
+            return;
+        };
+
+        *byte ^= key.to_le_bytes()[*key_byte_pos];
+        *key_byte_pos += 1;
+        data = rest;
+    }
+
+    let mut chunks = data.chunks_exact_mut(CHUNK_SIZE);
+
+    if chunks.len() != 0 {
+        let first_key = if *key_byte_pos == 4 {
+            next_key_older(*key)
+        } else {
+            *key
+        };
+
+        let mut lanes = [first_key; LANES];
+
+        for idx in 1..LANES {
+            lanes[idx] = next_key_older(lanes[idx - 1]);
+        }
+
+        for chunk in &mut chunks {
+            for (word, lane) in chunk.chunks_exact_mut(4).zip(&lanes) {
+                xor_word(word, *lane);
+            }
+
+            *key = lanes[LANES - 1];
+
+            for lane in &mut lanes {
+                *lane = lane
+                    .wrapping_mul(LANE_MULTIPLIER)
+                    .wrapping_add(LANE_INCREMENT);
+            }
+        }
+
+        *key_byte_pos = 4;
+    }
+
+    let mut key_bytes = key.to_le_bytes();
+
+    for byte in chunks.into_remainder() {
+        if *key_byte_pos == 4 {
+            *key_byte_pos = 0;
+            *key = next_key_older(*key);
+            key_bytes = key.to_le_bytes();
+        }
+
+        *byte ^= key_bytes[*key_byte_pos];
+        *key_byte_pos += 1;
+    }
+}
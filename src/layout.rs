@@ -0,0 +1,299 @@
+use crate::{
+    ARCHIVE_HEADER, Archive, ArchiveFile, DecryptOptions, Decrypter, Engine,
+    ExtractError,
+    keystream::jump_key,
+    next_key_older,
+    scan::{Scanner, xor_path},
+};
+use alloc::{vec, vec::Vec};
+use core::mem;
+
+/// Layout of a single archive entry.
+///
+/// # Fields
+/// - `path` - Decrypted path to the file.
+/// - `offset` - Offset of the file's encrypted data from the start of the archive.
+/// - `key` - Key, which the file's data is encrypted with.
+/// - `stored_key` - Key, as it's stored in the entry metadata. Same as `key` for XP/VX archives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryLayout {
+    pub path: Vec<u8>,
+    pub offset: u64,
+    pub key: u32,
+    pub stored_key: u32,
+}
+
+/// Layout metadata of an encrypted archive, that [`Archive::to_bytes`] doesn't preserve.
+///
+/// Captured from the original archive, and passed to [`Archive::to_bytes_with_layout`], it allows to re-encrypt the archive byte-for-byte, if the files weren't modified. That makes binary diffs between the original and the re-encrypted archives minimal.
+///
+/// # Fields
+/// - `engine` - Engine of the archive.
+/// - `base_key` - Key, stored after the VX Ace archive header, or initial key of XP/VX archive.
+/// - `metadata_key` - Key, which VX Ace metadata is encrypted with. Same as `base_key` for XP/VX archives.
+/// - `entries` - Layout of the entries, in the order they're stored in the archive metadata.
+/// - `trailer` - Raw bytes between the VX Ace metadata terminator offset and the first entry data. RPG Maker writes a full entry there, of which only the offset is read. Empty for XP/VX archives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveLayout {
+    pub engine: Engine,
+    pub base_key: u32,
+    pub metadata_key: u32,
+    pub entries: Vec<EntryLayout>,
+    pub trailer: Vec<u8>,
+}
+
+impl ArchiveLayout {
+    /// Captures the layout of the encrypted archive, without modifying `archive_data`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`crate::ArchiveIndex::new`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{Archive, ArchiveLayout};
+    /// use std::fs::{read, write};
+    ///
+    /// let data = read("C:/Game/Game.rgss3a").unwrap();
+    /// let layout = ArchiveLayout::new(&data).unwrap();
+    ///
+    /// let mut archive = Archive::open(&data).unwrap();
+    /// archive.replace(b"Data\\Map001.rvdata2", read("translation/Map001.rvdata2").unwrap());
+    ///
+    /// // Only the data of `Map001.rvdata2` and the offsets after it are changed.
+    /// write("C:/Game/Game.rgss3a", archive.to_bytes_with_layout(&layout)).unwrap();
+    /// ```
+    pub fn new(archive_data: &[u8]) -> Result<Self, ExtractError> {
+        Self::with_options(archive_data, &DecryptOptions::default())
+    }
+
+    /// Same as [`ArchiveLayout::new`], but derives keys according to `options`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`crate::ArchiveIndex::new`].
+    pub fn with_options(
+        archive_data: &[u8],
+        options: &DecryptOptions,
+    ) -> Result<Self, ExtractError> {
+        let mut scanner = Scanner::with_options(archive_data, options)?;
+        let format_engine = scanner.engine();
+        let mut engine = format_engine;
+        let metadata_key = scanner.key();
+        let mut entries = Vec::new();
+
+        // Options may override the base key, but the stored one must be written back
+        let base_key = if engine.is_vx_ace() {
+            let key_start = ARCHIVE_HEADER.len() + 1;
+            let mut key_bytes = [0; 4];
+            key_bytes.copy_from_slice(&archive_data[key_start..key_start + 4]);
+            u32::from_le_bytes(key_bytes)
+        } else {
+            scanner.base_key()
+        };
+
+        let mut engine_detected = engine.is_vx_ace();
+
+        for raw_entry in &mut scanner {
+            let raw_entry = raw_entry?;
+
+            let mut path = archive_data[raw_entry.path.clone()].to_vec();
+            raw_entry.xor_path(format_engine, &mut path);
+
+            if !engine_detected {
+                if let Some(detected) = engine.detect_older(&path) {
+                    engine = detected;
+                    engine_detected = true;
+                }
+            }
+
+            entries.push(EntryLayout {
+                path,
+                offset: raw_entry.offset,
+                key: raw_entry.key,
+                stored_key: raw_entry.stored_key,
+            });
+        }
+
+        let trailer = if engine.is_vx_ace() {
+            let trailer_start = scanner.pos();
+            let data_start = entries
+                .iter()
+                .map(|entry| entry.offset as usize)
+                .min()
+                .unwrap_or(archive_data.len());
+
+            archive_data
+                .get(trailer_start..data_start)
+                .unwrap_or_default()
+                .to_vec()
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            engine,
+            base_key,
+            metadata_key,
+            entries,
+            trailer,
+        })
+    }
+}
+
+/// Archive file with the layout of the entry, that it replaces.
+struct LayoutFile<'f, 'a> {
+    file: &'f ArchiveFile<'a>,
+    layout: Option<&'f EntryLayout>,
+    offset: u64,
+}
+
+impl Archive<'_> {
+    /// Same as [`Archive::to_bytes`], but preserves the layout of the original archive:
+    ///
+    /// - Files are stored in the order of `layout` entries. Files, that are not in `layout`, are appended to the end. If `layout` has duplicate paths, the file is stored at the first of them.
+    /// - Base key, metadata key and entry keys are reused. Files, that are not in `layout`, use the metadata key.
+    /// - Entry data is stored in the order of the original offsets.
+    /// - VX Ace metadata trailer is written as is.
+    ///
+    /// If the files weren't modified, the output is identical to the archive, that `layout` was captured from, as long as the original archive has no gaps between entry data.
+    ///
+    /// # Parameters
+    /// - `layout`: Layout of the original archive. Its engine is used as the target engine.
+    #[must_use]
+    pub fn to_bytes_with_layout(&self, layout: &ArchiveLayout) -> Vec<u8> {
+        let mut in_layout = vec![false; self.len()];
+
+        let mut files = layout
+            .entries
+            .iter()
+            .filter_map(|entry_layout| {
+                let idx = self.position(&entry_layout.path)?;
+
+                // Archives with duplicate paths are opened into a single file, which is only written once
+                if mem::replace(&mut in_layout[idx], true) {
+                    return None;
+                }
+
+                Some(LayoutFile {
+                    file: &self.files()[idx],
+                    layout: Some(entry_layout),
+                    offset: 0,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        files.extend(
+            self.files()
+                .iter()
+                .zip(in_layout)
+                .filter(|(_, in_layout)| !in_layout)
+                .map(|(file, _)| LayoutFile {
+                    file,
+                    layout: None,
+                    offset: 0,
+                }),
+        );
+
+        if layout.engine.is_vx_ace() {
+            encrypt_vxace(&mut files, layout)
+        } else {
+            encrypt_older(&files, layout)
+        }
+    }
+}
+
+fn encrypt_vxace(files: &mut [LayoutFile], layout: &ArchiveLayout) -> Vec<u8> {
+    let key = layout.metadata_key;
+
+    // Header, base key, metadata of every entry, terminator offset and trailer
+    let metadata_size = ARCHIVE_HEADER.len()
+        + 1
+        + 4
+        + files
+            .iter()
+            .map(|file| 16 + file.file.path.len())
+            .sum::<usize>()
+        + 4
+        + layout.trailer.len();
+
+    let mut data_order = (0..files.len()).collect::<Vec<_>>();
+    data_order.sort_by_key(|&idx| {
+        files[idx].layout.map_or(u64::MAX, |layout| layout.offset)
+    });
+
+    let mut offset = metadata_size as u64;
+
+    for idx in data_order.iter().copied() {
+        files[idx].offset = offset;
+        offset += files[idx].file.data.len() as u64;
+    }
+
+    let mut archive_buffer = Vec::with_capacity(offset as usize);
+    archive_buffer.extend_from_slice(ARCHIVE_HEADER);
+    archive_buffer.push(layout.engine.version());
+    archive_buffer.extend_from_slice(&layout.base_key.to_le_bytes());
+
+    for file in files.iter() {
+        let stored_key = file.layout.map_or(0, |layout| layout.stored_key);
+
+        for u32 in [
+            file.offset as u32 ^ key,
+            file.file.data.len() as u32 ^ key,
+            stored_key,
+            file.file.path.len() as u32 ^ key,
+        ] {
+            archive_buffer.extend_from_slice(&u32.to_le_bytes());
+        }
+
+        let path_start = archive_buffer.len();
+        archive_buffer.extend_from_slice(&file.file.path);
+        xor_path(layout.engine, key, &mut archive_buffer[path_start..]);
+    }
+
+    // Terminator offset is xor'd against itself into 0 when decrypting
+    archive_buffer.extend_from_slice(&key.to_le_bytes());
+    archive_buffer.extend_from_slice(&layout.trailer);
+
+    for idx in data_order {
+        let file = &files[idx];
+        let data_key = file.layout.map_or(key, |layout| layout.key);
+
+        let data_start = archive_buffer.len();
+        archive_buffer.extend_from_slice(&file.file.data);
+        Decrypter::xor_data(data_key, &mut archive_buffer[data_start..]);
+    }
+
+    archive_buffer
+}
+
+fn encrypt_older(files: &[LayoutFile], layout: &ArchiveLayout) -> Vec<u8> {
+    let mut key = layout.base_key;
+    let mut archive_buffer = vec![];
+    archive_buffer.extend_from_slice(ARCHIVE_HEADER);
+    archive_buffer.push(layout.engine.version());
+
+    for file in files {
+        let path = &file.file.path;
+        let data = &file.file.data;
+
+        archive_buffer
+            .extend_from_slice(&(path.len() as u32 ^ key).to_le_bytes());
+        key = next_key_older(key);
+
+        let path_start = archive_buffer.len();
+        archive_buffer.extend_from_slice(path);
+        xor_path(layout.engine, key, &mut archive_buffer[path_start..]);
+        key = jump_key(key, path.len() as u64);
+
+        archive_buffer
+            .extend_from_slice(&(data.len() as u32 ^ key).to_le_bytes());
+        key = next_key_older(key);
+
+        let data_start = archive_buffer.len();
+        archive_buffer.extend_from_slice(data);
+        Decrypter::xor_data(key, &mut archive_buffer[data_start..]);
+    }
+
+    archive_buffer
+}
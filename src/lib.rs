@@ -4,46 +4,118 @@
 #![allow(clippy::cast_possible_truncation)]
 #![allow(clippy::cast_possible_wrap)]
 #![allow(clippy::cast_sign_loss)]
-#![allow(clippy::deref_addrof)]
-#![allow(invalid_reference_casting)]
+// Memory maps can't be created without `unsafe`, so it's only allowed in the `mmap` module
+#![cfg_attr(not(feature = "mmap"), forbid(unsafe_code))]
+#![cfg_attr(feature = "mmap", deny(unsafe_code))]
 #![doc = include_str!("../README.md")]
 
-use core::{
-    default::Default,
-    iter::{self, Iterator},
-};
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "alloc")]
+mod archive;
+mod asset;
+#[cfg(feature = "alloc")]
+mod encoding;
+mod entries;
+#[cfg(feature = "std")]
+mod extract;
+#[cfg(feature = "alloc")]
+mod index;
+mod key;
+mod keystream;
+#[cfg(feature = "alloc")]
+mod layout;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "std")]
+mod pack;
+#[cfg(feature = "rayon")]
+mod par;
+#[cfg(feature = "alloc")]
+mod patch;
+mod probe;
+#[cfg(feature = "std")]
+mod reader;
+mod scan;
+mod view;
+#[cfg(feature = "std")]
+mod writer;
+
+#[cfg(feature = "alloc")]
+use alloc::{borrow::Cow, vec::Vec};
+use core::{default::Default, iter::Iterator};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use strum_macros::{Display, EnumIs};
 use thiserror::Error;
 
+#[cfg(feature = "alloc")]
+pub use archive::{Archive, ArchiveFile};
+pub use asset::{
+    ASSET_HEADER, ASSET_HEADER_SIZE, AssetDecrypter, AssetError, AssetKey,
+    MV_M4A_EXT, MV_OGG_EXT, MV_PNG_EXT, MZ_M4A_EXT, MZ_OGG_EXT, MZ_PNG_EXT,
+    asset_extension,
+};
+#[cfg(feature = "alloc")]
+pub use encoding::PathEncoding;
+pub use entries::DecryptedEntries;
+#[cfg(feature = "std")]
+pub use extract::{
+    ExtractFileError, ExtractOptions, ExtractedFile, sanitize_entry_path,
+};
+#[cfg(feature = "alloc")]
+pub use index::ArchiveIndex;
+pub use key::{DecryptOptions, EncryptOptions, EntryKeys};
+pub use keystream::KeyStream;
+#[cfg(feature = "alloc")]
+pub use layout::{ArchiveLayout, EntryLayout};
+#[cfg(feature = "mmap")]
+pub use mmap::{MappedArchive, MappedArchiveMut};
+#[cfg(feature = "std")]
+pub use pack::{PackError, PackOptions, pack_dir};
+#[cfg(feature = "alloc")]
+pub use patch::{
+    PATCH_MAGIC, PATCH_VERSION, Patch, PatchError, PatchOperation,
+};
+#[cfg(feature = "std")]
+pub use probe::probe_file;
+pub use probe::{ArchiveInfo, probe};
+#[cfg(feature = "std")]
+pub use reader::{ArchiveReader, EntryReader, ReadError};
+pub use view::{ArchiveView, EntryLocation, EntryLocations};
+#[cfg(feature = "std")]
+pub use writer::{ArchiveWriter, EntryHeader, WriteError};
+
+#[inline]
 fn memcpy(dst: &mut [u8], src: &[u8]) {
-    unsafe {
-        core::ptr::copy_nonoverlapping(
-            src.as_ptr(),
-            dst.as_mut_ptr(),
-            src.len(),
-        );
-    }
-}
-
-enum SeekFrom {
-    Start(u64),
-    Current(i64),
+    dst[..src.len()].copy_from_slice(src);
 }
 
 macro_rules! sizeof {
     ($t:ty) => {{ core::mem::size_of::<$t>() }};
 }
 
-const ARCHIVE_HEADER: &[u8; 7] = b"RGSSAD\0";
+pub(crate) const ARCHIVE_HEADER: &[u8; 7] = b"RGSSAD\0";
 
 const OLDER_DECRYPTION_KEY: u32 = 0xDEAD_CAFE;
-const ENCRYPTION_KEY: u32 = 0;
+
+#[inline]
+const fn next_key_older(key: u32) -> u32 {
+    key.wrapping_mul(7).wrapping_add(3)
+}
+
+#[inline]
+const fn next_key_vxace(key: u32) -> u32 {
+    key.wrapping_mul(9).wrapping_add(3)
+}
 
 pub const XP_RGSSAD_EXT: &str = "rgssad";
 pub const VX_RGSS2A_EXT: &str = "rgss2a";
 pub const VXACE_RGSS3A_EXT: &str = "rgss3a";
+pub const XP_RXDATA_EXT: &str = "rxdata";
+pub const VX_RVDATA_EXT: &str = "rvdata";
+pub const VXACE_RVDATA2_EXT: &str = "rvdata2";
 
 #[derive(Debug, Error)]
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
@@ -56,418 +128,297 @@ pub enum ExtractError {
         "Invalid game engine byte: {0}. Expected `1` for XP/VX or `3` for VX Ace."
     )]
     InvalidEngine(u8),
+    #[error(
+        "Unexpected end of archive at offset {offset}: {needed} more bytes needed."
+    )]
+    UnexpectedEof { offset: u64, needed: usize },
+    #[error(
+        "Data of entry {index} at offset {offset} with size {size} is out of archive bounds."
+    )]
+    EntryOutOfBounds {
+        index: usize,
+        offset: u64,
+        size: usize,
+    },
+    #[error(
+        "Path of entry {index} has size {size}, which exceeds the remaining archive data."
+    )]
+    PathTooLong { index: usize, size: usize },
+    #[error(
+        "No key, that decrypts the archive into plausible entries, was found."
+    )]
+    KeyNotFound,
+    #[error(
+        "Data of entry {index} overlaps archive metadata or data of another entry."
+    )]
+    OverlappingEntries { index: usize },
+    #[error(
+        "Output buffer of {available} bytes is too small: {needed} bytes needed."
+    )]
+    BufferTooSmall { needed: usize, available: usize },
 }
 
-#[derive(Debug, Display, EnumIs, Clone, Copy, PartialEq)]
+/// RPG Maker engine, that the archive belongs to.
+///
+/// XP and VX share the same archive format with version byte `1`, so the header alone cannot tell them apart. They're told apart by the extensions of data files among archive entries (`.rxdata` for XP and `.rvdata` for VX), and archives without data files are reported as [`Engine::XP`].
+#[derive(Debug, Display, EnumIs, Clone, Copy, PartialEq, Eq)]
 pub enum Engine {
-    #[strum(to_string = "XP/VX")]
-    Older = 1,
+    #[strum(to_string = "XP")]
+    XP,
+    #[strum(to_string = "VX")]
+    VX,
     #[strum(to_string = "VXAce")]
-    VXAce = 3,
+    VXAce,
 }
 
-/// Struct representing decrypted file.
-///
-/// # Fields
-/// - `path` - Represents path to the decrypted file. For example, graphics files are stored in Graphics/DIR, e.g. Graphics/Actors/Actor1.png.
-///
-/// Note, that `path` is represented by [`&[u8]`] because it may contain non-UTF-8 sequences, e.g. Japanese Shift JIS text. In that case, it's up to you how to handle the path.
-///
-/// - `data` - Represents content of the file.
-pub struct ArchiveEntry<'a> {
-    pub path: &'a [u8],
-    pub data: &'a [u8],
-}
-
-/// A struct responsible for decrypting and extracting files from encrypted game archives.
-pub struct Decrypter<'a> {
-    engine: Engine,
-    key: u32,
-    key_bytes: [u8; sizeof!(u32)],
-
-    // Decryption members
-    data: &'a mut [u8],
-    pos: usize,
-    len: usize,
-}
-
-impl<'a> Decrypter<'a> {
-    /// Creates a new [`Decrypter`] with empty buffer.
+impl Engine {
+    /// Returns `true` if the engine uses the older XP/VX archive format.
     #[must_use]
-    pub fn new() -> Self {
-        Self {
-            engine: Engine::Older,
-            key: OLDER_DECRYPTION_KEY,
-            key_bytes: OLDER_DECRYPTION_KEY.to_le_bytes(),
-
-            data: &mut [],
-            pos: 0,
-            len: 0,
-        }
-    }
-
     #[inline]
-    fn update_key(&mut self, new_key: u32) {
-        self.key = new_key;
-        self.key_bytes = new_key.to_le_bytes();
-    }
-
-    #[inline]
-    fn update_key_older(&mut self) {
-        self.update_key(self.key.wrapping_mul(7).wrapping_add(3));
-    }
-
-    #[inline]
-    fn update_key_vxace(&mut self) {
-        self.update_key(self.key.wrapping_mul(9).wrapping_add(3));
-    }
-
-    #[inline]
-    #[track_caller]
-    fn read_bytes(&mut self, count: usize) -> &[u8] {
-        self.pos += count;
-        &self.data[self.pos - count..self.pos]
+    pub const fn is_older(&self) -> bool {
+        matches!(self, Self::XP | Self::VX)
     }
 
+    /// Returns the version byte, that is written after the archive header: `1` for XP/VX and `3` for VX Ace.
+    #[must_use]
     #[inline]
-    fn read_u32(&mut self) -> u32 {
-        let chunk = self.read_bytes(sizeof!(u32));
-        u32::from_le_bytes(unsafe {
-            *chunk.as_ptr().cast::<[u8; sizeof!(u32)]>()
-        })
+    pub const fn version(&self) -> u8 {
+        match self {
+            Self::XP | Self::VX => 1,
+            Self::VXAce => 3,
+        }
     }
 
     #[inline]
-    fn read_byte(&mut self) -> u8 {
-        self.pos += 1;
-        self.data[self.pos - 1]
+    pub(crate) const fn from_version(version: u8) -> Option<Self> {
+        match version {
+            1 => Some(Self::XP),
+            3 => Some(Self::VXAce),
+            _ => None,
+        }
     }
 
-    #[inline]
-    fn seek_byte(&mut self, from: SeekFrom) {
-        self.pos = match from {
-            SeekFrom::Start(offset) => offset as usize,
-            SeekFrom::Current(offset) => self.pos + offset as usize,
-        };
-    }
+    /// Returns the engine, which uses the given archive file extension, ignoring ASCII case and the leading dot.
+    ///
+    /// # Example
+    /// ```
+    /// use rpgmad_lib::Engine;
+    ///
+    /// assert_eq!(Engine::from_extension("rgss2a"), Some(Engine::VX));
+    /// assert_eq!(Engine::from_extension(".RGSS3A"), Some(Engine::VXAce));
+    /// assert_eq!(Engine::from_extension("zip"), None);
+    /// ```
+    #[must_use]
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        let extension = extension.strip_prefix('.').unwrap_or(extension);
 
-    #[inline]
-    /// Decrypts u32 if `u32` is encrypted, encrypts u32 if `u32` is decrypted.
-    fn xor_u32_vxace(&self, u32: u32) -> u32 {
-        u32 ^ self.key
+        [Self::XP, Self::VX, Self::VXAce]
+            .into_iter()
+            .find(|engine| engine.extension().eq_ignore_ascii_case(extension))
     }
 
-    #[inline]
-    /// Decrypts u32 if `u32` is encrypted, encrypts u32 if `u32` is decrypted.
-    fn xor_u32_older(&mut self, u32: u32) -> u32 {
-        let decrypted = u32 ^ self.key;
-
-        if self.engine.is_older() {
-            self.update_key_older();
+    /// Returns the archive file extension, that the engine uses, without the leading dot.
+    #[must_use]
+    pub const fn extension(&self) -> &'static str {
+        match self {
+            Self::XP => XP_RGSSAD_EXT,
+            Self::VX => VX_RGSS2A_EXT,
+            Self::VXAce => VXACE_RGSS3A_EXT,
         }
-
-        decrypted
     }
 
-    #[inline]
-    /// Decrypts path if `path_data` is encrypted, encrypts path if `path_data` is decrypted.
-    fn xor_path_vxace(&mut self, path_data: &mut [u8]) {
-        for (idx, byte) in
-            (unsafe { &*(path_data as *mut [u8]) }).iter().enumerate()
-        {
-            // Compiler is smart and can optimize this modulo into `& 0b11`.
-            // Since modulo is more self-descriptive, let it be here.
-            path_data[idx] = byte ^ self.key_bytes[idx % 4];
-        }
+    /// Returns the engine from the archive file name, e.g. `Game.rgss2a`, ignoring ASCII case.
+    ///
+    /// # Example
+    /// ```
+    /// use rpgmad_lib::Engine;
+    ///
+    /// assert_eq!(Engine::from_file_name("Game.rgss2a"), Some(Engine::VX));
+    /// assert_eq!(Engine::from_file_name("Game.exe"), None);
+    /// ```
+    #[must_use]
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let (_, extension) = file_name.rsplit_once('.')?;
+        Self::from_extension(extension)
     }
 
-    #[inline]
-    /// Decrypts path if `path_data` is encrypted, encrypts path if `path_data` is decrypted.
-    fn xor_path_older(&mut self, path_data: &mut [u8]) {
-        for (idx, byte) in
-            (unsafe { &*(path_data as *mut [u8]) }).iter().enumerate()
-        {
-            path_data[idx] = byte ^ self.key as u8;
-            self.update_key_older();
+    /// Returns the extension of data files, that the engine uses, without the leading dot: `rxdata` for XP, `rvdata` for VX and `rvdata2` for VX Ace.
+    #[must_use]
+    pub const fn data_extension(&self) -> &'static str {
+        match self {
+            Self::XP => XP_RXDATA_EXT,
+            Self::VX => VX_RVDATA_EXT,
+            Self::VXAce => VXACE_RVDATA2_EXT,
         }
     }
 
-    #[inline]
-    /// Decrypts data if `data` is encrypted, encrypts data if `data` is decrypted.
-    fn xor_data(mut key: u32, data: &mut [u8]) {
-        let mut key_bytes = key.to_le_bytes();
-        let mut key_byte_pos = 0;
-
-        // Decrypting data
-        for (idx, data_byte) in
-            (unsafe { &*(data as *mut [u8]) }).iter().enumerate()
-        {
-            if key_byte_pos == 4 {
-                key_byte_pos = 0;
-                key = key.wrapping_mul(7).wrapping_add(3);
-                key_bytes = key.to_le_bytes();
-            }
-
-            data[idx] = data_byte ^ key_bytes[key_byte_pos];
-            key_byte_pos += 1;
-        }
+    /// Returns the engine, whose data files have the extension of the given archive entry `path`, ignoring ASCII case.
+    ///
+    /// # Example
+    /// ```
+    /// use rpgmad_lib::Engine;
+    ///
+    /// assert_eq!(Engine::from_data_path(b"Data\\Map001.rxdata"), Some(Engine::XP));
+    /// assert_eq!(Engine::from_data_path(b"Graphics\\Titles\\Title.png"), None);
+    /// ```
+    #[must_use]
+    pub fn from_data_path(path: &[u8]) -> Option<Self> {
+        let dot_pos = path.iter().rposition(|&byte| byte == b'.')?;
+        let extension = &path[dot_pos + 1..];
+
+        [Self::XP, Self::VX, Self::VXAce]
+            .into_iter()
+            .find(|engine| {
+                engine
+                    .data_extension()
+                    .as_bytes()
+                    .eq_ignore_ascii_case(extension)
+            })
     }
 
+    /// Returns XP or VX engine, if `self` is one of them, and `path` is a data file of XP or VX.
     #[inline]
-    fn parse_header(&mut self) -> Result<(), ExtractError> {
-        let header = self.read_bytes(ARCHIVE_HEADER.len());
-
-        if header != ARCHIVE_HEADER {
-            return Err(ExtractError::InvalidHeader(unsafe {
-                *header.as_ptr().cast::<[u8; 7]>()
-            }));
+    pub(crate) fn detect_older(self, path: &[u8]) -> Option<Self> {
+        if !self.is_older() {
+            return None;
         }
 
-        let engine_type = self.read_byte();
-
-        self.engine = match engine_type {
-            1 => Engine::Older,
-            3 => Engine::VXAce,
-            _ => {
-                return Err(ExtractError::InvalidEngine(engine_type));
-            }
-        };
-
-        Ok(())
+        Self::from_data_path(path).filter(Self::is_older)
     }
+}
 
-    #[inline]
-    #[track_caller]
-    fn decrypt_entries(&'a mut self) -> impl Iterator<Item = ArchiveEntry<'a>> {
-        if self.engine.is_vx_ace() {
-            // Default key is not ever used and overwritten.
-            let key = self.read_u32();
-            self.update_key(key);
-            self.update_key_vxace();
-        }
-
-        iter::from_fn(move  || {
-            let mut u32: u32;
-
-            if self.engine.is_vx_ace() {
-                u32 = self.read_u32();
-                let data_offset = self.xor_u32_vxace(u32) as u64;
-
-                // End of data
-                if data_offset == 0 {
-                    return None;
-                }
-
-                u32 = self.read_u32();
-                let data_size = self.xor_u32_vxace(u32) as usize;
-
-                u32 = self.read_u32();
-                let entry_key = self.xor_u32_vxace(u32);
-
-                u32 = self.read_u32();
-                let path_size = self.xor_u32_vxace(u32) as usize;
-
-                let path_data = unsafe {
-                    &mut *(self.read_bytes(path_size) as *const [u8]
-                        as *mut [u8])
-                };
-
-                self.xor_path_vxace(path_data);
-
-                // Store current position
-                let prev_pos = self.pos;
-
-                // Read data
-                self.seek_byte(SeekFrom::Start(data_offset));
-
-                let entry_data = unsafe {
-                    &mut *(self.read_bytes(data_size) as *const [u8]
-                        as *mut [u8])
-                };
-                Self::xor_data(entry_key, entry_data);
-
-                let entry = ArchiveEntry {
-                    path: path_data,
-                    data: entry_data,
-                };
-
-                // Restore position
-                self.seek_byte(SeekFrom::Start(prev_pos as u64));
-
-                Some(entry)
-            } else {
-                // End of data
-                if self.pos == self.len {
-                    return None;
-                }
-
-                u32 = self.read_u32();
-                let path_size = self.xor_u32_older(u32) as usize;
-
-                let path_data = unsafe {
-                    &mut *(self.read_bytes(path_size) as *const [u8]
-                        as *mut [u8])
-                };
-
-                self.xor_path_older(path_data);
-
-                u32 = self.read_u32();
-                let data_size = self.xor_u32_older(u32) as usize;
-                let data_offset = self.pos as u64;
-                let entry_key = self.key;
-
-                // Skip data block
-                self.seek_byte(SeekFrom::Current(data_size as i64));
-
-                // Store current position
-                let prev_pos = self.pos;
-
-                // Seek back to the data and read it
-                self.seek_byte(SeekFrom::Start(data_offset));
-
-                let entry_data = unsafe {
-                    &mut *(self.read_bytes(data_size) as *const [u8]
-                        as *mut [u8])
-                };
-                Self::xor_data(entry_key, entry_data);
-
-                let entry = ArchiveEntry {
-                    path: path_data,
-                    data: entry_data,
-                };
+/// Struct representing decrypted file.
+///
+/// # Fields
+/// - `path` - Represents path to the decrypted file. For example, graphics files are stored in Graphics/DIR, e.g. Graphics/Actors/Actor1.png.
+///
+/// Note, that `path` is represented by [`&[u8]`] because it may contain non-UTF-8 sequences, e.g. Japanese Shift JIS text. Use `ArchiveEntry::path_str` or `ArchiveEntry::decode_path` to decode it.
+///
+/// - `data` - Represents content of the file.
+pub struct ArchiveEntry<'a> {
+    pub path: &'a [u8],
+    pub data: &'a [u8],
+}
 
-                // Restore position
-                self.seek_byte(SeekFrom::Start(prev_pos as u64));
+#[cfg(feature = "alloc")]
+impl<'a> ArchiveEntry<'a> {
+    /// Decodes `path` with the given `encoding`.
+    ///
+    /// # Returns
+    /// - Decoded path.
+    /// - [`None`], if `path` cannot be decoded with `encoding`.
+    #[must_use]
+    pub fn path_str(&self, encoding: PathEncoding) -> Option<Cow<'a, str>> {
+        encoding.decode(self.path)
+    }
 
-                Some(entry)
-            }
+    /// Decodes `path`, detecting whether it's encoded in UTF-8 or CP932. If it's neither, invalid sequences are replaced with `U+FFFD`.
+    ///
+    /// See [`PathEncoding::detect`].
+    #[cfg(feature = "encoding")]
+    #[must_use]
+    pub fn decode_path(&self) -> Cow<'a, str> {
+        PathEncoding::Auto.decode(self.path).unwrap_or_else(|| {
+            alloc::string::String::from_utf8_lossy(self.path)
         })
     }
+}
 
-    fn encrypt_entries(
-        &mut self,
-        entries: &[ArchiveEntry],
-        archive_buffer: &mut [u8],
-    ) {
-        let mut offset = 8;
-
-        if self.engine.is_vx_ace() {
-            self.update_key(ENCRYPTION_KEY);
-
-            memcpy(&mut archive_buffer[offset..], &self.key_bytes);
-            offset += 4;
-
-            self.update_key_vxace();
-        }
-
-        if self.engine.is_vx_ace() {
-            // First we write metadata: content size, key, path size and path itself
-            for entry in entries {
-                // Placeholder offset, we'll modify it later
-                memcpy(&mut archive_buffer[offset..], &0u32.to_le_bytes());
-                offset += 4;
-
-                let data_size = entry.data.len() as u32;
-                let encoded_data_size = self.xor_u32_vxace(data_size);
-                memcpy(
-                    &mut archive_buffer[offset..],
-                    &encoded_data_size.to_le_bytes(),
-                );
-                offset += 4;
-
-                // self.key ^ self.key = 0
-                memcpy(
-                    &mut archive_buffer[offset..],
-                    &ENCRYPTION_KEY.to_le_bytes(),
-                );
-                offset += 4;
-
-                let path_size = entry.path.len() as u32;
-                let encoded_path_size = self.xor_u32_vxace(path_size);
-                memcpy(
-                    &mut archive_buffer[offset..],
-                    &encoded_path_size.to_le_bytes(),
-                );
-                offset += 4;
-
-                memcpy(&mut archive_buffer[offset..], entry.path);
-                self.xor_path_vxace(&mut archive_buffer[offset..]);
-                offset += entry.path.len();
-            }
-
-            // Write the key, when decrypthing it will be xor'd against itself which will produce 0, and decryption will stop.
-            memcpy(&mut archive_buffer[offset..], &self.key.to_le_bytes());
-            offset += 4;
-
-            let mut placeholder_offset = 12;
-
-            // Write the actual contents and modify the offsets with the offsets of the contents
-            for entry in entries {
-                let data_offset = offset as u32;
-                let encrypted_data_offset = self.xor_u32_vxace(data_offset);
-
-                archive_buffer[placeholder_offset..placeholder_offset + 4]
-                    .copy_from_slice(&encrypted_data_offset.to_le_bytes());
-
-                placeholder_offset += 16 + entry.path.len();
-
-                memcpy(&mut archive_buffer[offset..], entry.data);
-                Self::xor_data(self.key, &mut archive_buffer[offset..]);
-                offset += entry.data.len();
-            }
-        } else {
-            for entry in entries {
-                let path_size = entry.path.len() as u32;
-
-                let encoded_path_size = self.xor_u32_older(path_size);
-                memcpy(
-                    &mut archive_buffer[offset..],
-                    &encoded_path_size.to_le_bytes(),
-                );
-
-                memcpy(&mut archive_buffer[offset..], entry.path);
-                offset += entry.path.len();
+/// Struct representing metadata of an encrypted file, without its data.
+///
+/// # Fields
+/// - `path` - Decrypted path to the file. See [`ArchiveEntry`] for the notes about its encoding.
+/// - `offset` - Offset of the file's encrypted data from the start of the archive.
+/// - `size` - Size of the file's data in bytes.
+/// - `key` - Key, which the file's data is encrypted with.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryInfo {
+    pub path: Vec<u8>,
+    pub offset: u64,
+    pub size: u32,
+    pub key: u32,
+}
 
-                self.xor_path_older(&mut archive_buffer[offset..]);
+/// A struct responsible for decrypting and extracting files from encrypted game archives.
+///
+/// [`Decrypter`] only holds the options, and is cheap to copy. Decryption state is owned by the iterator, that [`Decrypter::decrypt`] returns, so a single [`Decrypter`] can decrypt any number of archives, while their entries are alive.
+#[derive(Debug, Clone, Copy)]
+pub struct Decrypter {
+    options: DecryptOptions,
+}
 
-                let data_size = entry.data.len() as u32;
-                let encoded_data_size = self.xor_u32_older(data_size);
-                memcpy(
-                    &mut archive_buffer[offset..],
-                    &encoded_data_size.to_le_bytes(),
-                );
-                offset += 4;
+impl Decrypter {
+    /// Creates a new [`Decrypter`] with default options.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_options(DecryptOptions::default())
+    }
 
-                memcpy(&mut archive_buffer[offset..], entry.data);
-                Self::xor_data(self.key, &mut archive_buffer[offset..]);
-                offset += entry.data.len();
-            }
-        }
+    /// Creates a new [`Decrypter`] with `options`, which control how keys are derived.
+    ///
+    /// Options are only used for decryption, and don't affect [`Decrypter::encrypt`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{Decrypter, DecryptOptions};
+    /// use std::fs::read;
+    ///
+    /// let mut data = read("C:/Game/Game.rgss3a").unwrap();
+    /// let options = DecryptOptions {
+    ///     base_key: Some(0x1234_5678),
+    ///     ..Default::default()
+    /// };
+    ///
+    /// let decrypter = Decrypter::with_options(options);
+    /// let entries = decrypter.decrypt(&mut data).unwrap();
+    /// ```
+    #[must_use]
+    pub fn with_options(options: DecryptOptions) -> Self {
+        Self { options }
     }
 
-    fn reset(&mut self, data: &'a mut [u8]) {
-        self.len = data.len();
-        self.data = data;
-        self.pos = 0;
+    #[inline]
+    /// Decrypts data if `data` is encrypted, encrypts data if `data` is decrypted.
+    pub(crate) fn xor_data(mut key: u32, data: &mut [u8]) {
+        Self::xor_data_from(&mut key, &mut 0, data);
+    }
 
-        self.engine = Engine::Older;
-        self.key = OLDER_DECRYPTION_KEY;
-        self.key_bytes = OLDER_DECRYPTION_KEY.to_le_bytes();
+    #[inline]
+    /// Same as [`Decrypter::xor_data`], but starts from `key_byte_pos` byte of `key`.
+    ///
+    /// After the call, `key` and `key_byte_pos` hold the keystream state past the end of `data`, so the next chunk of the same file can be processed with them.
+    pub(crate) fn xor_data_from(
+        key: &mut u32,
+        key_byte_pos: &mut usize,
+        data: &mut [u8],
+    ) {
+        keystream::xor_keystream(key, key_byte_pos, data);
     }
 
     /// Returns an iterator over decrypted [`ArchiveEntry`] entries.
     ///
+    /// Metadata of all entries is bounds-checked against `archive_data` up front, so truncated or corrupted archives produce an error instead of panicking, and the number of entries is known before anything is decrypted. Only the entries, that the iterator actually yields, are decrypted. After the first error, iterator stops yielding entries.
+    ///
     /// # Parameters
     /// - `archive_data`: The content of the archive file. This data is modified in-place, and requires to be a mutable reference.
     ///
     /// # Returns
-    /// - [`Iterator<Item = ArchiveEntry>`] if files were successfully decrypted.
+    /// - [`DecryptedEntries`] iterator if archive header was successfully parsed.
     /// - [`ExtractError`] otherwise.
     ///
     /// # Errors
     ///
     /// - [`ExtractError::InvalidHeader`] for invalid header.
     /// - [`ExtractError::InvalidEngine`] for invalid header engine type byte.
+    /// - [`ExtractError::UnexpectedEof`] if archive is too short to contain the header, or the key of VX Ace archive.
+    ///
+    /// Iterator itself yields, after the entries before the corrupted one:
+    ///
+    /// - [`ExtractError::UnexpectedEof`] if entry metadata is truncated.
+    /// - [`ExtractError::PathTooLong`] if entry path exceeds the remaining archive data.
+    /// - [`ExtractError::EntryOutOfBounds`] if entry data lies outside of the archive.
+    /// - [`ExtractError::OverlappingEntries`] if entry data overlaps metadata or data of the previous entries, or entry metadata overlaps data of the previous entries. See [`DecryptedEntries`] for the notes about builds without `alloc` feature.
     ///
     /// # Example
     /// ```no_run
@@ -475,10 +426,11 @@ impl<'a> Decrypter<'a> {
     /// use std::{path::PathBuf, fs::{read, write, create_dir_all}};
     ///
     /// let mut data = read("C:/Game/Game.rgss3a").unwrap();
-    /// let mut decrypter = Decrypter::new();
+    /// let decrypter = Decrypter::new();
     /// let decrypted_entries = decrypter.decrypt(&mut data).unwrap();
     ///
     /// for entry in decrypted_entries {
+    ///     let entry = entry.unwrap();
     ///     let path = String::from_utf8_lossy(&entry.path);
     ///     let output_path = PathBuf::from("C:/Game").join(path.as_ref());
     ///
@@ -490,13 +442,11 @@ impl<'a> Decrypter<'a> {
     /// }
     /// ```
     #[inline]
-    pub fn decrypt(
-        &'a mut self,
-        archive_data: &'a mut [u8],
-    ) -> Result<impl Iterator<Item = ArchiveEntry<'a>>, ExtractError> {
-        self.reset(archive_data);
-        self.parse_header()?;
-        Ok(self.decrypt_entries())
+    pub fn decrypt<'d>(
+        &self,
+        archive_data: &'d mut [u8],
+    ) -> Result<DecryptedEntries<'d>, ExtractError> {
+        DecryptedEntries::new(archive_data, &self.options)
     }
 
     /// Returns the size for the encrypted buffer of archive entries in bytes.
@@ -511,6 +461,7 @@ impl<'a> Decrypter<'a> {
     /// # Example
     /// See [`Decrypter::encrypt`].
     ///
+    #[must_use]
     pub fn encrypted_buffer_size(
         archive_entries: &[ArchiveEntry],
         engine: Engine,
@@ -587,23 +538,73 @@ impl<'a> Decrypter<'a> {
     /// Decrypter::new().encrypt(&archive_entries, Engine::VXAce, &mut archive_buffer);
     /// write("./Game.rgss3a", archive_buffer).unwrap();
     /// ```
-    #[must_use]
     #[inline]
     pub fn encrypt(
-        &mut self,
+        &self,
         archive_entries: &[ArchiveEntry],
         engine: Engine,
         archive_buffer: &mut [u8],
     ) {
-        memcpy(archive_buffer, ARCHIVE_HEADER);
-        archive_buffer[7] = engine as u8;
+        self.encrypt_with_options(
+            archive_entries,
+            engine,
+            &mut EncryptOptions::default(),
+            archive_buffer,
+        );
+    }
 
-        self.engine = engine;
-        self.encrypt_entries(archive_entries, archive_buffer);
+    /// Same as [`Decrypter::encrypt`], but encrypts VX Ace archives with the keys, chosen according to `options`.
+    ///
+    /// `archive_buffer` must have the size that [`Decrypter::encrypted_buffer_size`] returns, as keys don't affect the archive size.
+    ///
+    /// # Parameters
+    /// - `archive_entries`: Archive entries to encrypt.
+    /// - `engine`: Target archive engine.
+    /// - `options`: Encryption keys options.
+    /// - `archive_buffer`: Buffer to write encrypted data into.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{Decrypter, EncryptOptions, Engine, EntryKeys, ArchiveEntry};
+    ///
+    /// let archive_entries = [ArchiveEntry {
+    ///     path: b"Data\\System.rvdata2",
+    ///     data: b"\x04\x080",
+    /// }];
+    ///
+    /// let mut options = EncryptOptions {
+    ///     base_key: 0x1234_5678,
+    ///     entry_keys: EntryKeys::Derived,
+    /// };
+    ///
+    /// let mut archive_buffer =
+    ///     vec![0; Decrypter::encrypted_buffer_size(&archive_entries, Engine::VXAce)];
+    /// Decrypter::new().encrypt_with_options(&archive_entries, Engine::VXAce, &mut options, &mut archive_buffer);
+    /// ```
+    pub fn encrypt_with_options(
+        &self,
+        archive_entries: &[ArchiveEntry],
+        engine: Engine,
+        options: &mut EncryptOptions,
+        archive_buffer: &mut [u8],
+    ) {
+        memcpy(archive_buffer, ARCHIVE_HEADER);
+        archive_buffer[7] = engine.version();
+
+        MetadataEncrypter::new(engine).encrypt_entries(
+            archive_entries,
+            options,
+            archive_buffer,
+            |archive_buffer, offset, key, data| {
+                let block = &mut archive_buffer[offset..offset + data.len()];
+                memcpy(block, data);
+                Self::xor_data(key, block);
+            },
+        );
     }
 }
 
-impl Default for Decrypter<'_> {
+impl Default for Decrypter {
     /// Returns a new [`Decrypter`] with default parameters.
     ///
     /// Equivalent to calling [`Decrypter::new`].
@@ -611,3 +612,200 @@ impl Default for Decrypter<'_> {
         Self::new()
     }
 }
+
+/// Key state of the archive metadata, that [`Decrypter::encrypt`] writes.
+///
+/// Encryption advances the keys on its own state, so [`Decrypter`] doesn't require a mutable borrow to encrypt.
+struct MetadataEncrypter {
+    engine: Engine,
+    key: u32,
+    key_bytes: [u8; sizeof!(u32)],
+}
+
+impl MetadataEncrypter {
+    fn new(engine: Engine) -> Self {
+        Self {
+            engine,
+            key: OLDER_DECRYPTION_KEY,
+            key_bytes: OLDER_DECRYPTION_KEY.to_le_bytes(),
+        }
+    }
+
+    #[inline]
+    fn update_key(&mut self, new_key: u32) {
+        self.key = new_key;
+        self.key_bytes = new_key.to_le_bytes();
+    }
+
+    #[inline]
+    fn update_key_older(&mut self) {
+        self.update_key(next_key_older(self.key));
+    }
+
+    #[inline]
+    fn update_key_vxace(&mut self) {
+        self.update_key(next_key_vxace(self.key));
+    }
+
+    #[inline]
+    /// Decrypts u32 if `u32` is encrypted, encrypts u32 if `u32` is decrypted.
+    fn xor_u32_vxace(&self, u32: u32) -> u32 {
+        u32 ^ self.key
+    }
+
+    #[inline]
+    /// Decrypts u32 if `u32` is encrypted, encrypts u32 if `u32` is decrypted.
+    fn xor_u32_older(&mut self, u32: u32) -> u32 {
+        let decrypted = u32 ^ self.key;
+
+        if self.engine.is_older() {
+            self.update_key_older();
+        }
+
+        decrypted
+    }
+
+    #[inline]
+    /// Decrypts path if `path_data` is encrypted, encrypts path if `path_data` is decrypted.
+    fn xor_path_vxace(&mut self, path_data: &mut [u8]) {
+        for (idx, byte) in path_data.iter_mut().enumerate() {
+            // Compiler is smart and can optimize this modulo into `& 0b11`.
+            // Since modulo is more self-descriptive, let it be here.
+            *byte ^= self.key_bytes[idx % 4];
+        }
+    }
+
+    #[inline]
+    /// Decrypts path if `path_data` is encrypted, encrypts path if `path_data` is decrypted.
+    fn xor_path_older(&mut self, path_data: &mut [u8]) {
+        for byte in path_data {
+            *byte ^= self.key as u8;
+            self.update_key_older();
+        }
+    }
+
+    /// Writes encrypted archive metadata, and calls `write_data` with the archive buffer, offset, key and data of every entry.
+    fn encrypt_entries(
+        mut self,
+        entries: &[ArchiveEntry],
+        options: &mut EncryptOptions,
+        archive_buffer: &mut [u8],
+        mut write_data: impl FnMut(&mut [u8], usize, u32, &[u8]),
+    ) {
+        let mut offset = 8;
+
+        if self.engine.is_vx_ace() {
+            self.update_key(options.base_key);
+
+            memcpy(&mut archive_buffer[offset..], &self.key_bytes);
+            offset += 4;
+
+            self.update_key_vxace();
+        }
+
+        if self.engine.is_vx_ace() {
+            let metadata_key = self.key;
+            let mut entry_key = metadata_key;
+
+            // First we write metadata: content size, key, path size and path itself
+            for entry in entries {
+                entry_key = options.next_entry_key(metadata_key, entry_key);
+
+                // Placeholder offset, we'll modify it later
+                memcpy(&mut archive_buffer[offset..], &0u32.to_le_bytes());
+                offset += 4;
+
+                let data_size = entry.data.len() as u32;
+                let encoded_data_size = self.xor_u32_vxace(data_size);
+                memcpy(
+                    &mut archive_buffer[offset..],
+                    &encoded_data_size.to_le_bytes(),
+                );
+                offset += 4;
+
+                let encoded_entry_key = self.xor_u32_vxace(entry_key);
+                memcpy(
+                    &mut archive_buffer[offset..],
+                    &encoded_entry_key.to_le_bytes(),
+                );
+                offset += 4;
+
+                let path_size = entry.path.len() as u32;
+                let encoded_path_size = self.xor_u32_vxace(path_size);
+                memcpy(
+                    &mut archive_buffer[offset..],
+                    &encoded_path_size.to_le_bytes(),
+                );
+                offset += 4;
+
+                memcpy(&mut archive_buffer[offset..], entry.path);
+                self.xor_path_vxace(
+                    &mut archive_buffer[offset..offset + entry.path.len()],
+                );
+                offset += entry.path.len();
+            }
+
+            // Write the key, when decrypthing it will be xor'd against itself which will produce 0, and decryption will stop.
+            // VX Ace writes the full entry metadata here, so write zero data size, key and path size after it as well.
+            for _ in 0..4 {
+                memcpy(&mut archive_buffer[offset..], &self.key.to_le_bytes());
+                offset += 4;
+            }
+
+            let mut placeholder_offset = 12;
+
+            // Write the actual contents and modify the offsets with the offsets of the contents
+            for entry in entries {
+                let data_offset = offset as u32;
+                let encrypted_data_offset = self.xor_u32_vxace(data_offset);
+
+                archive_buffer[placeholder_offset..placeholder_offset + 4]
+                    .copy_from_slice(&encrypted_data_offset.to_le_bytes());
+
+                // Entry keys may come from RNG, so read them back instead of generating again
+                let mut encoded_entry_key = [0; sizeof!(u32)];
+                encoded_entry_key.copy_from_slice(
+                    &archive_buffer
+                        [placeholder_offset + 8..placeholder_offset + 12],
+                );
+                let entry_key =
+                    self.xor_u32_vxace(u32::from_le_bytes(encoded_entry_key));
+
+                placeholder_offset += 16 + entry.path.len();
+
+                write_data(archive_buffer, offset, entry_key, entry.data);
+                offset += entry.data.len();
+            }
+        } else {
+            self.update_key(OLDER_DECRYPTION_KEY);
+
+            for entry in entries {
+                let path_size = entry.path.len() as u32;
+
+                let encoded_path_size = self.xor_u32_older(path_size);
+                memcpy(
+                    &mut archive_buffer[offset..],
+                    &encoded_path_size.to_le_bytes(),
+                );
+                offset += 4;
+
+                memcpy(&mut archive_buffer[offset..], entry.path);
+                self.xor_path_older(
+                    &mut archive_buffer[offset..offset + entry.path.len()],
+                );
+                offset += entry.path.len();
+
+                let data_size = entry.data.len() as u32;
+                let encoded_data_size = self.xor_u32_older(data_size);
+                memcpy(
+                    &mut archive_buffer[offset..],
+                    &encoded_data_size.to_le_bytes(),
+                );
+                offset += 4;
+
+                write_data(archive_buffer, offset, self.key, entry.data);
+                offset += entry.data.len();
+            }
+        }
+    }
+}
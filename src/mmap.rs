@@ -0,0 +1,158 @@
+use crate::{ArchiveView, DecryptOptions, Engine, ReadError};
+use memmap2::{Mmap, MmapMut, MmapOptions};
+use std::{
+    fs::File,
+    io,
+    ops::{Deref, DerefMut},
+    path::Path,
+};
+
+/// Archive file, mapped into memory read-only.
+///
+/// Only the pages, that are actually touched, are loaded from disk, so even multi-gigabyte archives can be decrypted without reading them into memory. Entries are listed and decrypted with [`MappedArchive::view`], see [`ArchiveView`].
+///
+/// The archive file must not be modified by this or other processes while it's mapped, which is why [`MappedArchive::open`] is `unsafe`.
+pub struct MappedArchive {
+    map: Mmap,
+    options: DecryptOptions,
+    engine: Engine,
+    len: usize,
+}
+
+impl MappedArchive {
+    /// Maps the archive file into memory read-only, and validates its metadata.
+    ///
+    /// # Parameters
+    /// - `path`: Path to the archive file.
+    ///
+    /// # Errors
+    ///
+    /// - [`ReadError::Io`] if opening or mapping the file fails.
+    /// - [`ReadError::Extract`] with the same errors as [`ArchiveView::new`].
+    ///
+    /// # Safety
+    ///
+    /// The archive file must not be modified or truncated, by this or any other process, while the returned [`MappedArchive`] is alive. Otherwise, its content may change under the shared references, that [`MappedArchive::view`] and [`MappedArchive::as_bytes`] return, which is undefined behavior.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::MappedArchive;
+    ///
+    /// // SAFETY: Game archive isn't modified while it's mapped.
+    /// let archive = unsafe { MappedArchive::open("C:/Game/Game.rgss3a") }.unwrap();
+    /// let view = archive.view();
+    ///
+    /// if let Some(entry) = view.get(b"Audio\\BGM\\Theme1.ogg") {
+    ///     let mut buffer = vec![0; entry.size as usize];
+    ///     let theme = view.decrypt_entry_into(&entry, &mut buffer).unwrap();
+    /// }
+    /// ```
+    #[allow(unsafe_code)]
+    pub unsafe fn open(path: impl AsRef<Path>) -> Result<Self, ReadError> {
+        // SAFETY: Upheld by the caller.
+        unsafe { Self::open_with_options(path, &DecryptOptions::default()) }
+    }
+
+    /// Same as [`MappedArchive::open`], but derives keys according to `options`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`MappedArchive::open`].
+    ///
+    /// # Safety
+    ///
+    /// Same as [`MappedArchive::open`].
+    #[allow(unsafe_code)]
+    pub unsafe fn open_with_options(
+        path: impl AsRef<Path>,
+        options: &DecryptOptions,
+    ) -> Result<Self, ReadError> {
+        let file = File::open(path)?;
+
+        // SAFETY: Caller guarantees, that the file isn't modified while it's mapped.
+        let map = unsafe { Mmap::map(&file)? };
+
+        let view = ArchiveView::with_options(&map, options)?;
+        let (engine, len) = (view.engine(), view.len());
+
+        Ok(Self {
+            map,
+            options: *options,
+            engine,
+            len,
+        })
+    }
+
+    /// Returns the [`ArchiveView`] of the mapped archive, which lists and decrypts its entries without modifying the file.
+    #[must_use]
+    pub fn view(&self) -> ArchiveView<'_> {
+        ArchiveView::from_parts(&self.map, self.options, self.engine, self.len)
+    }
+
+    /// Returns the raw encrypted content of the archive.
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+/// Archive file, mapped into memory copy-on-write.
+///
+/// Dereferences to the mutable content of the archive, so it can be decrypted in-place with [`crate::Decrypter::decrypt`]. Modified pages are private to the process, and are never written back to the file.
+///
+/// The archive file must not be modified by this or other processes while it's mapped, which is why [`MappedArchiveMut::open`] is `unsafe`.
+pub struct MappedArchiveMut {
+    map: MmapMut,
+}
+
+impl MappedArchiveMut {
+    /// Maps the archive file into memory copy-on-write.
+    ///
+    /// # Parameters
+    /// - `path`: Path to the archive file.
+    ///
+    /// # Errors
+    ///
+    /// - [`io::Error`] if opening or mapping the file fails.
+    ///
+    /// # Safety
+    ///
+    /// The archive file must not be modified or truncated, by this or any other process, while the returned [`MappedArchiveMut`] is alive. Otherwise, content of the pages, that weren't written to yet, may change under the references, that it dereferences to, which is undefined behavior.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{Decrypter, MappedArchiveMut};
+    ///
+    /// // SAFETY: Game archive isn't modified while it's mapped.
+    /// let mut archive = unsafe { MappedArchiveMut::open("C:/Game/Game.rgss3a") }.unwrap();
+    /// let decrypter = Decrypter::new();
+    ///
+    /// for entry in decrypter.decrypt(&mut archive).unwrap() {
+    ///     let entry = entry.unwrap();
+    ///     println!("{}: {} bytes", String::from_utf8_lossy(entry.path), entry.data.len());
+    /// }
+    /// ```
+    #[allow(unsafe_code)]
+    pub unsafe fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+
+        // SAFETY: Caller guarantees, that the file isn't modified while it's mapped.
+        let map = unsafe { MmapOptions::new().map_copy(&file)? };
+
+        Ok(Self { map })
+    }
+}
+
+impl Deref for MappedArchiveMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+impl DerefMut for MappedArchiveMut {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.map
+    }
+}
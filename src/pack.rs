@@ -0,0 +1,241 @@
+use crate::{Archive, Engine, PathEncoding};
+use std::{
+    fs::{canonicalize, metadata, read, read_dir},
+    io,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/// Files, that RPG Maker never puts into the archive.
+const GAME_FILES: [&str; 5] =
+    ["Game.exe", "Game.ini", "*.rgssad", "*.rgss2a", "*.rgss3a"];
+
+/// Options for [`pack_dir`].
+///
+/// Glob patterns are matched against paths relative to the game directory, with `/` separators, ignoring ASCII case. `*` and `?` match within a single path component, and `**` matches any number of components.
+///
+/// # Fields
+/// - `include` - Patterns of files to pack. Defaults to `Data/**` and `Graphics/**`, like RPG Maker's "Compress Game Data" does.
+/// - `exclude` - Patterns of files to skip, even if they match `include`. Empty by default.
+/// - `include_audio` - Whether to also pack `Audio/**`. RPG Maker keeps audio files outside of the archive, so defaults to `false`.
+/// - `skip_game_files` - Whether to skip `Game.exe`, `Game.ini` and existing archives in the game directory root. Defaults to `true`.
+/// - `encoding` - Encoding to store paths in. Defaults to [`PathEncoding::Utf8`]. Japanese RPG Maker stores paths in CP932, which requires `encoding` feature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackOptions {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub include_audio: bool,
+    pub skip_game_files: bool,
+    pub encoding: PathEncoding,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            include: vec!["Data/**".into(), "Graphics/**".into()],
+            exclude: Vec::new(),
+            include_audio: false,
+            skip_game_files: true,
+            encoding: PathEncoding::Utf8,
+        }
+    }
+}
+
+impl PackOptions {
+    fn include_patterns(&self) -> impl Iterator<Item = &str> {
+        self.include
+            .iter()
+            .map(String::as_str)
+            .chain(self.include_audio.then_some("Audio/**"))
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let included = self.include_patterns().any(|pattern| {
+            glob_match(pattern.as_bytes(), path.as_bytes(), false)
+        });
+
+        let excluded = self
+            .exclude
+            .iter()
+            .map(String::as_str)
+            .chain(GAME_FILES.iter().copied().filter(|_| self.skip_game_files))
+            .any(|pattern| {
+                glob_match(pattern.as_bytes(), path.as_bytes(), false)
+            });
+
+        included && !excluded
+    }
+
+    /// Checks whether files in the directory at `path` can match some include pattern.
+    fn may_match_in(&self, path: &str) -> bool {
+        let dir = format!("{path}/");
+
+        self.include_patterns()
+            .any(|pattern| glob_match(pattern.as_bytes(), dir.as_bytes(), true))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PackError {
+    #[error("File name {0} is not valid UTF-8.")]
+    InvalidFileName(PathBuf),
+    #[error("Path {0} cannot be encoded with {1:?} encoding.")]
+    UnencodablePath(PathBuf, PathEncoding),
+    #[error("{path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> PackError + '_ {
+    move |source| PackError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// Matches `path` against glob `pattern`, ignoring ASCII case.
+///
+/// If `prefix` is set, also matches if `path` is the beginning of some path, that matches `pattern`.
+fn glob_match(pattern: &[u8], path: &[u8], prefix: bool) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        _ if prefix && path.is_empty() => true,
+        // `**/` also matches zero components
+        [b'*', b'*', b'/' | b'\\', rest @ ..] => {
+            glob_match(rest, path, prefix)
+                || (0..path.len()).any(|idx| {
+                    path[idx] == b'/'
+                        && glob_match(rest, &path[idx + 1..], prefix)
+                })
+        }
+        [b'*', b'*', rest @ ..] => {
+            (0..=path.len()).any(|idx| glob_match(rest, &path[idx..], prefix))
+        }
+        [b'*', rest @ ..] => (0..=path.len())
+            .take_while(|&idx| idx == 0 || path[idx - 1] != b'/')
+            .any(|idx| glob_match(rest, &path[idx..], prefix)),
+        [b'?', rest @ ..] => {
+            matches!(path, [byte, ..] if *byte != b'/')
+                && glob_match(rest, &path[1..], prefix)
+        }
+        [b'/' | b'\\', rest @ ..] => {
+            matches!(path, [b'/', ..]) && glob_match(rest, &path[1..], prefix)
+        }
+        [byte, rest @ ..] => {
+            matches!(path, [path_byte, ..] if path_byte.eq_ignore_ascii_case(byte))
+                && glob_match(rest, &path[1..], prefix)
+        }
+    }
+}
+
+/// Collects files under `dir`, that match `options`, with their paths relative to the game directory.
+///
+/// `ancestors` holds canonical paths of `dir` and its parents, so symbolic links to them aren't followed into infinite recursion.
+fn collect_files(
+    dir: &Path,
+    relative: &str,
+    options: &PackOptions,
+    ancestors: &mut Vec<PathBuf>,
+    files: &mut Vec<(String, PathBuf)>,
+) -> Result<(), PackError> {
+    for dir_entry in read_dir(dir).map_err(io_error(dir))? {
+        let dir_entry = dir_entry.map_err(io_error(dir))?;
+        let path = dir_entry.path();
+        let file_name = dir_entry.file_name();
+
+        // Names, that aren't valid UTF-8, are only rejected, if they could be packed
+        let name = file_name.to_string_lossy();
+        let relative_path = if relative.is_empty() {
+            name.into_owned()
+        } else {
+            format!("{relative}/{name}")
+        };
+
+        let is_file_match = options.matches(&relative_path);
+        let may_match_in = options.may_match_in(&relative_path);
+
+        if !is_file_match && !may_match_in {
+            continue;
+        }
+
+        if file_name.to_str().is_none() {
+            return Err(PackError::InvalidFileName(path));
+        }
+
+        // Follow symbolic links
+        let file_type = metadata(&path).map_err(io_error(&path))?.file_type();
+
+        if file_type.is_dir() && may_match_in {
+            let canonical = canonicalize(&path).map_err(io_error(&path))?;
+
+            if ancestors.contains(&canonical) {
+                continue;
+            }
+
+            ancestors.push(canonical);
+            collect_files(&path, &relative_path, options, ancestors, files)?;
+            ancestors.pop();
+        } else if file_type.is_file() && is_file_match {
+            files.push((relative_path, path));
+        }
+    }
+
+    Ok(())
+}
+
+/// Packs files from the game directory into an encrypted archive.
+///
+/// Files are stored with backslash-separated paths relative to `root`, e.g. `Data\System.rvdata2`, sorted by path, so packing the same directory always produces the same archive.
+///
+/// Symbolic links are followed, except for links to `root` or the directories, that contain them. Directories, that no include pattern can match files in, are skipped without reading them.
+///
+/// # Parameters
+/// - `root`: Game directory.
+/// - `engine`: Target archive engine.
+/// - `options`: Options, that control which files are packed.
+///
+/// # Returns
+/// - Encrypted archive content.
+///
+/// # Errors
+///
+/// - [`PackError::InvalidFileName`] if a name of a file, that could be packed, or of a directory, that could contain such files, is not valid UTF-8.
+/// - [`PackError::UnencodablePath`] if a path cannot be encoded with [`PackOptions::encoding`].
+/// - [`PackError::Io`] if reading a directory or a file fails.
+///
+/// # Example
+/// ```no_run
+/// use rpgmad_lib::{pack_dir, Engine, PackOptions};
+/// use std::fs::write;
+///
+/// let archive = pack_dir("C:/Game", Engine::VXAce, &PackOptions::default()).unwrap();
+/// write("C:/Game/Game.rgss3a", archive).unwrap();
+/// ```
+pub fn pack_dir(
+    root: impl AsRef<Path>,
+    engine: Engine,
+    options: &PackOptions,
+) -> Result<Vec<u8>, PackError> {
+    let root = root.as_ref();
+    let mut ancestors = vec![canonicalize(root).map_err(io_error(root))?];
+    let mut files = Vec::new();
+    collect_files(root, "", options, &mut ancestors, &mut files)?;
+
+    for (relative_path, _) in &mut files {
+        *relative_path = relative_path.replace('/', "\\");
+    }
+
+    files.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut archive = Archive::new();
+
+    for (relative_path, path) in files {
+        let Some(encoded_path) = options.encoding.encode(&relative_path) else {
+            return Err(PackError::UnencodablePath(path, options.encoding));
+        };
+
+        let data = read(&path).map_err(io_error(&path))?;
+        archive.insert(encoded_path.into_owned(), data);
+    }
+
+    Ok(archive.to_bytes(engine))
+}
@@ -0,0 +1,271 @@
+use crate::{
+    ARCHIVE_HEADER, ArchiveEntry, Decrypter, EncryptOptions, Engine,
+    ExtractError, MetadataEncrypter,
+    scan::{Scanner, xor_path},
+};
+use core::mem;
+use rayon::prelude::*;
+
+/// Contents of a [`Block`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    /// Archive header or metadata fields of an entry, which are only checked for overlaps, and never decrypted.
+    Metadata,
+    Path,
+    Data,
+}
+
+/// Region of the archive buffer, that is encrypted independently of the others.
+struct Block {
+    index: usize,
+    start: usize,
+    end: usize,
+    key: u32,
+    kind: BlockKind,
+}
+
+impl Block {
+    #[inline]
+    fn metadata(index: usize, start: usize, end: usize) -> Self {
+        Self {
+            index,
+            start,
+            end,
+            key: 0,
+            kind: BlockKind::Metadata,
+        }
+    }
+}
+
+/// Sorts `blocks` by their starts, and checks that they don't overlap.
+///
+/// # Errors
+///
+/// - [`ExtractError::OverlappingEntries`] if some blocks overlap.
+fn sort_blocks(blocks: &mut Vec<Block>) -> Result<(), ExtractError> {
+    // Empty blocks never overlap, and are left empty
+    blocks.retain(|block| block.start != block.end);
+    blocks.sort_by_key(|block| block.start);
+
+    for pair in blocks.windows(2) {
+        if pair[1].start < pair[0].end {
+            // Report the entry, whose data overlaps, rather than the entry, whose metadata is overlapped
+            let block = if pair[0].kind == BlockKind::Data
+                && pair[1].kind != BlockKind::Data
+            {
+                &pair[0]
+            } else {
+                &pair[1]
+            };
+
+            return Err(ExtractError::OverlappingEntries {
+                index: block.index,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits `buffer` into disjoint subslices, covered by `blocks`, which must be sorted and must not overlap.
+fn split_blocks(
+    buffer: &mut [u8],
+    blocks: Vec<Block>,
+) -> Vec<(Block, &mut [u8])> {
+    let mut slices = Vec::with_capacity(blocks.len());
+    let mut rest = buffer;
+    let mut consumed = 0;
+
+    for block in blocks {
+        let (_, tail) =
+            mem::take(&mut rest).split_at_mut(block.start - consumed);
+        let (slice, tail) = tail.split_at_mut(block.end - block.start);
+
+        rest = tail;
+        consumed = block.end;
+        slices.push((block, slice));
+    }
+
+    slices
+}
+
+impl Decrypter {
+    /// Same as [`Decrypter::decrypt`], but decrypts all entries concurrently with [`rayon`].
+    ///
+    /// Entry table is parsed and validated first, and then paths and data of all entries are decrypted in parallel, as they're stored in disjoint regions of `archive_data`. Entry data must not overlap the archive header, metadata fields and paths of the entries, or data of other entries. If the archive is corrupted, `archive_data` is left untouched.
+    ///
+    /// # Parameters
+    /// - `archive_data`: The content of the archive file. This data is modified in-place, and requires to be a mutable reference.
+    ///
+    /// # Errors
+    ///
+    /// - Same as [`Decrypter::decrypt`], including the errors, that its iterator yields.
+    /// - [`ExtractError::OverlappingEntries`] if entry data overlaps metadata or data of another entry.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::Decrypter;
+    /// use std::fs::read;
+    ///
+    /// let mut data = read("C:/Game/Game.rgss3a").unwrap();
+    /// let entries = Decrypter::new().decrypt_par(&mut data).unwrap();
+    ///
+    /// for entry in entries {
+    ///     println!("{}: {} bytes", String::from_utf8_lossy(entry.path), entry.data.len());
+    /// }
+    /// ```
+    pub fn decrypt_par<'d>(
+        &self,
+        archive_data: &'d mut [u8],
+    ) -> Result<Vec<ArchiveEntry<'d>>, ExtractError> {
+        let mut scanner = Scanner::with_options(archive_data, &self.options)?;
+        let engine = scanner.engine();
+
+        let mut blocks = vec![Block::metadata(0, 0, scanner.pos())];
+        let mut entry_count = 0;
+
+        for (index, raw_entry) in (&mut scanner).enumerate() {
+            let raw_entry = raw_entry?;
+            let path = raw_entry.path;
+
+            // VX Ace metadata fields precede the path, XP/VX path size precedes it, and data size follows it
+            if engine.is_vx_ace() {
+                blocks.push(Block::metadata(
+                    index,
+                    path.start - 16,
+                    path.start,
+                ));
+            } else {
+                blocks.push(Block::metadata(index, path.start - 4, path.start));
+                blocks.push(Block::metadata(index, path.end, path.end + 4));
+            }
+
+            blocks.push(Block {
+                index,
+                start: path.start,
+                end: path.end,
+                key: raw_entry.path_key,
+                kind: BlockKind::Path,
+            });
+
+            let start = raw_entry.offset as usize;
+
+            blocks.push(Block {
+                index,
+                start,
+                end: start + raw_entry.size as usize,
+                key: raw_entry.key,
+                kind: BlockKind::Data,
+            });
+
+            entry_count += 1;
+        }
+
+        sort_blocks(&mut blocks)?;
+        let mut slices = split_blocks(archive_data, blocks);
+
+        slices
+            .par_iter_mut()
+            .for_each(|(block, slice)| match block.kind {
+                BlockKind::Metadata => {}
+                BlockKind::Path => xor_path(engine, block.key, slice),
+                BlockKind::Data => Self::xor_data(block.key, slice),
+            });
+
+        let mut entries = (0..entry_count)
+            .map(|_| ArchiveEntry {
+                path: &[],
+                data: &[],
+            })
+            .collect::<Vec<_>>();
+
+        for (block, slice) in slices {
+            match block.kind {
+                BlockKind::Metadata => {}
+                BlockKind::Path => entries[block.index].path = slice,
+                BlockKind::Data => entries[block.index].data = slice,
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Same as [`Decrypter::encrypt`], but encrypts data of all entries concurrently with [`rayon`].
+    ///
+    /// Produced archive is identical to the one produced by [`Decrypter::encrypt`].
+    ///
+    /// # Parameters
+    /// - `archive_entries`: Archive entries to encrypt.
+    /// - `engine`: Target archive engine.
+    /// - `archive_buffer`: Buffer to write encrypted data into. Must have the size, that [`Decrypter::encrypted_buffer_size`] returns.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{ArchiveEntry, Decrypter, Engine};
+    /// use std::fs::{read, write};
+    ///
+    /// let data = read("Audio/BGM/Theme1.ogg").unwrap();
+    /// let archive_entries = [ArchiveEntry {
+    ///     path: b"Audio\\BGM\\Theme1.ogg",
+    ///     data: &data,
+    /// }];
+    ///
+    /// let mut archive_buffer =
+    ///     vec![0; Decrypter::encrypted_buffer_size(&archive_entries, Engine::VXAce)];
+    /// Decrypter::new().encrypt_par(&archive_entries, Engine::VXAce, &mut archive_buffer);
+    /// write("./Game.rgss3a", archive_buffer).unwrap();
+    /// ```
+    pub fn encrypt_par(
+        &self,
+        archive_entries: &[ArchiveEntry],
+        engine: Engine,
+        archive_buffer: &mut [u8],
+    ) {
+        self.encrypt_with_options_par(
+            archive_entries,
+            engine,
+            &mut EncryptOptions::default(),
+            archive_buffer,
+        );
+    }
+
+    /// Same as [`Decrypter::encrypt_with_options`], but encrypts data of all entries concurrently with [`rayon`].
+    ///
+    /// Produced archive is identical to the one produced by [`Decrypter::encrypt_with_options`] with the same options.
+    pub fn encrypt_with_options_par(
+        &self,
+        archive_entries: &[ArchiveEntry],
+        engine: Engine,
+        options: &mut EncryptOptions,
+        archive_buffer: &mut [u8],
+    ) {
+        archive_buffer[..ARCHIVE_HEADER.len()].copy_from_slice(ARCHIVE_HEADER);
+        archive_buffer[ARCHIVE_HEADER.len()] = engine.version();
+
+        // Metadata is written serially, as XP/VX keys and VX Ace offsets depend on the previous entries
+        let mut blocks = Vec::with_capacity(archive_entries.len());
+
+        MetadataEncrypter::new(engine).encrypt_entries(
+            archive_entries,
+            options,
+            archive_buffer,
+            |_, offset, key, data| {
+                blocks.push(Block {
+                    index: blocks.len(),
+                    start: offset,
+                    end: offset + data.len(),
+                    key,
+                    kind: BlockKind::Data,
+                });
+            },
+        );
+
+        // Data blocks are laid out sequentially, so they're already sorted and never overlap
+        let slices = split_blocks(archive_buffer, blocks);
+
+        slices.into_par_iter().for_each(|(block, slice)| {
+            slice.copy_from_slice(archive_entries[block.index].data);
+            Self::xor_data(block.key, slice);
+        });
+    }
+}
@@ -0,0 +1,665 @@
+use crate::Archive;
+use alloc::{collections::BTreeMap, vec::Vec};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Signature, that every serialized [`Patch`] starts with.
+pub const PATCH_MAGIC: [u8; 8] = *b"RGSSPTCH";
+
+/// Version of the patch format, that is written after [`PATCH_MAGIC`].
+pub const PATCH_VERSION: u8 = 1;
+
+/// Size of the blocks, that are looked up in the source data when computing deltas. Shorter matches are stored as literal bytes.
+const BLOCK_SIZE: usize = 16;
+
+/// Maximum count of source blocks with the same hash, that are compared against the target.
+const MAX_CANDIDATES: usize = 8;
+
+/// Base of the polynomial rolling hash of blocks.
+const ROLLING_BASE: u32 = 0x0100_0193;
+
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+const OP_REMOVE: u8 = 0;
+const OP_ADD: u8 = 1;
+const OP_CHANGE: u8 = 2;
+
+const DELTA_COPY: u8 = 0;
+const DELTA_INSERT: u8 = 1;
+
+/// Size of the shortest delta copy instruction: tag, offset and size.
+const MIN_COPY_SIZE: usize = 3;
+
+#[derive(Debug, Error)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum PatchError {
+    #[error("Invalid patch header: {0:?}. Expected: RGSSPTCH")]
+    InvalidHeader([u8; 8]),
+    #[error("Unsupported patch format version: {0}. Expected: 1")]
+    UnsupportedVersion(u8),
+    #[error("Unexpected end of patch at offset {0}.")]
+    UnexpectedEof(usize),
+    #[error("Invalid variable-length integer at offset {0}.")]
+    InvalidVarint(usize),
+    #[error("Invalid operation tag {tag} at offset {offset}.")]
+    InvalidOperation { tag: u8, offset: usize },
+    #[error("Delta of entry {path:?} is corrupted.")]
+    InvalidDelta { path: Vec<u8> },
+    #[error(
+        "Entry {path:?}, that the patch changes or removes, doesn't exist."
+    )]
+    MissingEntry { path: Vec<u8> },
+    #[error(
+        "Source archive hash {actual:016x} doesn't match the expected {expected:016x}. The patch was made for a different archive."
+    )]
+    SourceMismatch { expected: u64, actual: u64 },
+    #[error(
+        "Patched archive hash {actual:016x} doesn't match the expected {expected:016x}. The patch is corrupted."
+    )]
+    TargetMismatch { expected: u64, actual: u64 },
+}
+
+/// A single change to the archive entries, stored in [`Patch`].
+///
+/// # Variants
+/// - `Remove` - Removes the entry with the given path.
+/// - `Add` - Adds a new entry with the given path and decrypted data.
+/// - `Change` - Changes data of the entry with the given path. `delta` is the binary diff between the decrypted source and target data, see [`Patch`] for its format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchOperation {
+    Remove { path: Vec<u8> },
+    Add { path: Vec<u8>, data: Vec<u8> },
+    Change { path: Vec<u8>, delta: Vec<u8> },
+}
+
+/// Entry-level binary patch between two archives.
+///
+/// Patches operate on decrypted entries, so they don't depend on archive keys and layout, and changed entries are stored as compact diffs of their decrypted data.
+///
+/// Hashes are 64-bit FNV-1a hashes of the archive content, see [`Archive::content_hash`]. They guard against applying the patch to a wrong archive and against corrupted patches, but are not cryptographically secure.
+///
+/// # Format
+///
+/// All integers are little-endian. `varint` is an unsigned LEB128 integer.
+///
+/// ```text
+/// magic:        8 bytes "RGSSPTCH"
+/// version:      u8 = 1
+/// source_hash:  u64
+/// target_hash:  u64
+/// op_count:     varint
+/// operations:   op_count times
+///     tag:      u8 (0 = Remove, 1 = Add, 2 = Change)
+///     path:     varint size, bytes
+///     data:     varint size, bytes (Add only)
+///     delta:    varint size, bytes (Change only)
+/// ```
+///
+/// Delta consists of the target data size and instructions, that are executed until the target data is complete:
+///
+/// ```text
+/// target_size:  varint
+/// instructions:
+///     0, offset: varint, size: varint   - copy `size` bytes from `offset` of the source data
+///     1, size: varint, bytes            - insert `size` literal bytes
+/// ```
+///
+/// # Fields
+/// - `source_hash` - Content hash of the archive, that the patch applies to.
+/// - `target_hash` - Content hash of the archive after patching.
+/// - `operations` - Changes to the archive entries. Removals come first, then additions and changes in the order of the target archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub source_hash: u64,
+    pub target_hash: u64,
+    pub operations: Vec<PatchOperation>,
+}
+
+#[inline]
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+
+    hash
+}
+
+impl Archive<'_> {
+    /// Returns the hash of the archive content: paths and data of all files, regardless of their order.
+    ///
+    /// The hash doesn't depend on the engine and keys, that the archive is encrypted with, so it identifies the same content in differently encrypted archives.
+    #[must_use]
+    pub fn content_hash(&self) -> u64 {
+        let mut files = self
+            .files()
+            .iter()
+            .map(|file| (file.path.as_ref(), file.data.as_ref()))
+            .collect::<Vec<_>>();
+        files.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+        sorted_content_hash(files)
+    }
+}
+
+/// Returns the content hash of `files`, which are `(path, data)` pairs, sorted by path.
+fn sorted_content_hash<'f>(
+    files: impl IntoIterator<Item = (&'f [u8], &'f [u8])>,
+) -> u64 {
+    files
+        .into_iter()
+        .fold(FNV_OFFSET_BASIS, |hash, (path, data)| {
+            let hash = fnv1a(hash, &(path.len() as u64).to_le_bytes());
+            let hash = fnv1a(hash, path);
+            let hash = fnv1a(hash, &(data.len() as u64).to_le_bytes());
+            fnv1a(hash, data)
+        })
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    buffer.push(value as u8);
+}
+
+fn write_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+/// Cursor over serialized patch or delta.
+struct PatchReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], PatchError> {
+        if self.data.len() - self.pos < count {
+            return Err(PatchError::UnexpectedEof(self.data.len()));
+        }
+
+        let bytes = &self.data[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, PatchError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u64(&mut self) -> Result<u64, PatchError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn read_varint(&mut self) -> Result<u64, PatchError> {
+        let offset = self.pos;
+        let mut value = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= u64::from(byte & 0x7F) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        // More than 64 bits
+        Err(PatchError::InvalidVarint(offset))
+    }
+
+    fn read_size(&mut self) -> Result<usize, PatchError> {
+        let offset = self.pos;
+        let size = self.read_varint()?;
+        usize::try_from(size).map_err(|_| PatchError::InvalidVarint(offset))
+    }
+
+    fn read_sized_bytes(&mut self) -> Result<&'a [u8], PatchError> {
+        let size = self.read_size()?;
+        self.read_bytes(size)
+    }
+}
+
+#[inline]
+fn block_hash(block: &[u8]) -> u32 {
+    block.iter().fold(0u32, |hash, &byte| {
+        hash.wrapping_mul(ROLLING_BASE)
+            .wrapping_add(u32::from(byte))
+    })
+}
+
+/// Computes the delta, that turns `source` into `target`.
+///
+/// Source is split into blocks, and the target is scanned with the rolling hash for matching blocks, which are extended as far as possible in both directions.
+fn compute_delta(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_varint(&mut delta, target.len() as u64);
+
+    let mut blocks = source
+        .chunks_exact(BLOCK_SIZE)
+        .enumerate()
+        .map(|(idx, block)| (block_hash(block), idx * BLOCK_SIZE))
+        .collect::<Vec<_>>();
+    blocks.sort_by_key(|&(hash, _)| hash);
+
+    // Multiplier of the byte, that leaves the rolling window
+    let leaving_multiplier = (1..BLOCK_SIZE)
+        .fold(1u32, |multiplier, _| multiplier.wrapping_mul(ROLLING_BASE));
+
+    let mut literal_start = 0;
+    let mut pos = 0;
+    let mut hash = None;
+
+    while pos + BLOCK_SIZE <= target.len() {
+        let current_hash = *hash
+            .get_or_insert_with(|| block_hash(&target[pos..pos + BLOCK_SIZE]));
+
+        let first = blocks.partition_point(|&(hash, _)| hash < current_hash);
+        let mut best: Option<(usize, usize, usize)> = None;
+
+        for &(_, offset) in blocks[first..]
+            .iter()
+            .take_while(|&&(hash, _)| hash == current_hash)
+            .take(MAX_CANDIDATES)
+        {
+            let forward = source[offset..]
+                .iter()
+                .zip(&target[pos..])
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            if forward < BLOCK_SIZE {
+                continue;
+            }
+
+            let backward = source[..offset]
+                .iter()
+                .rev()
+                .zip(target[literal_start..pos].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            let size = backward + forward;
+
+            if best.map_or(true, |(_, _, best_size)| best_size < size) {
+                best = Some((offset - backward, pos - backward, size));
+            }
+        }
+
+        if let Some((source_start, target_start, size)) = best {
+            if target_start > literal_start {
+                delta.push(DELTA_INSERT);
+                write_bytes(&mut delta, &target[literal_start..target_start]);
+            }
+
+            delta.push(DELTA_COPY);
+            write_varint(&mut delta, source_start as u64);
+            write_varint(&mut delta, size as u64);
+
+            pos = target_start + size;
+            literal_start = pos;
+            hash = None;
+        } else {
+            if pos + BLOCK_SIZE < target.len() {
+                hash = Some(
+                    current_hash
+                        .wrapping_sub(
+                            u32::from(target[pos])
+                                .wrapping_mul(leaving_multiplier),
+                        )
+                        .wrapping_mul(ROLLING_BASE)
+                        .wrapping_add(u32::from(target[pos + BLOCK_SIZE])),
+                );
+            }
+
+            pos += 1;
+        }
+    }
+
+    if literal_start < target.len() {
+        delta.push(DELTA_INSERT);
+        write_bytes(&mut delta, &target[literal_start..]);
+    }
+
+    delta
+}
+
+/// Applies `delta` to `source`.
+///
+/// Target size is checked against the size, that the remaining instructions can produce at most, and every instruction is checked not to exceed it, so corrupted deltas can't allocate more than they describe.
+///
+/// # Returns
+/// - Target data.
+/// - [`None`] if the delta is corrupted.
+fn apply_delta(source: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut reader = PatchReader {
+        data: delta,
+        pos: 0,
+    };
+
+    let target_size = reader.read_size().ok()?;
+
+    // Entry data size is stored as `u32`, and every copy instruction produces at most the whole source
+    let remaining = delta.len() - reader.pos;
+    let max_target_size = (remaining / MIN_COPY_SIZE)
+        .saturating_mul(source.len())
+        .saturating_add(remaining);
+
+    if target_size as u64 > u64::from(u32::MAX) || target_size > max_target_size
+    {
+        return None;
+    }
+
+    let mut target =
+        Vec::with_capacity(target_size.min(delta.len() + source.len()));
+
+    while target.len() < target_size {
+        let bytes = match reader.read_u8().ok()? {
+            DELTA_COPY => {
+                let offset = reader.read_size().ok()?;
+                let size = reader.read_size().ok()?;
+                let end = offset.checked_add(size)?;
+                source.get(offset..end)?
+            }
+            DELTA_INSERT => reader.read_sized_bytes().ok()?,
+            _ => return None,
+        };
+
+        if bytes.len() > target_size - target.len() {
+            return None;
+        }
+
+        target.extend_from_slice(bytes);
+    }
+
+    (target.len() == target_size && reader.is_empty()).then_some(target)
+}
+
+impl Patch {
+    /// Compares two archives and creates a patch, that turns `source` into `target`.
+    ///
+    /// # Parameters
+    /// - `source`: Original archive, e.g. opened with [`Archive::open`].
+    /// - `target`: Modified archive.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{Archive, Patch};
+    /// use std::fs::{read, write};
+    ///
+    /// let source = Archive::open(&read("original/Game.rgss3a").unwrap()).unwrap();
+    /// let target = Archive::open(&read("translated/Game.rgss3a").unwrap()).unwrap();
+    ///
+    /// write("translation.rgsspatch", Patch::diff(&source, &target).to_bytes()).unwrap();
+    /// ```
+    #[must_use]
+    pub fn diff(source: &Archive, target: &Archive) -> Self {
+        let source_files = source
+            .files()
+            .iter()
+            .map(|file| (file.path.as_ref(), file.data.as_ref()))
+            .collect::<BTreeMap<_, _>>();
+        let target_files = target
+            .files()
+            .iter()
+            .map(|file| (file.path.as_ref(), file.data.as_ref()))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut operations = source
+            .files()
+            .iter()
+            .filter(|file| !target_files.contains_key(file.path.as_ref()))
+            .map(|file| PatchOperation::Remove {
+                path: file.path.to_vec(),
+            })
+            .collect::<Vec<_>>();
+
+        for file in target.files() {
+            let path = file.path.to_vec();
+
+            match source_files.get(file.path.as_ref()) {
+                None => operations.push(PatchOperation::Add {
+                    path,
+                    data: file.data.to_vec(),
+                }),
+                Some(&source_data) if source_data != file.data.as_ref() => {
+                    operations.push(PatchOperation::Change {
+                        path,
+                        delta: compute_delta(source_data, &file.data),
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+
+        Self {
+            source_hash: source.content_hash(),
+            target_hash: target.content_hash(),
+            operations,
+        }
+    }
+
+    /// Serializes the patch into the format, described in [`Patch`].
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&PATCH_MAGIC);
+        buffer.push(PATCH_VERSION);
+        buffer.extend_from_slice(&self.source_hash.to_le_bytes());
+        buffer.extend_from_slice(&self.target_hash.to_le_bytes());
+        write_varint(&mut buffer, self.operations.len() as u64);
+
+        for operation in &self.operations {
+            match operation {
+                PatchOperation::Remove { path } => {
+                    buffer.push(OP_REMOVE);
+                    write_bytes(&mut buffer, path);
+                }
+                PatchOperation::Add { path, data } => {
+                    buffer.push(OP_ADD);
+                    write_bytes(&mut buffer, path);
+                    write_bytes(&mut buffer, data);
+                }
+                PatchOperation::Change { path, delta } => {
+                    buffer.push(OP_CHANGE);
+                    write_bytes(&mut buffer, path);
+                    write_bytes(&mut buffer, delta);
+                }
+            }
+        }
+
+        buffer
+    }
+
+    /// Parses the patch, serialized with [`Patch::to_bytes`].
+    ///
+    /// # Errors
+    ///
+    /// - [`PatchError::InvalidHeader`] for invalid header.
+    /// - [`PatchError::UnsupportedVersion`] for unknown format version.
+    /// - [`PatchError::UnexpectedEof`] if patch is truncated.
+    /// - [`PatchError::InvalidVarint`] for integer, that exceeds 64 bits.
+    /// - [`PatchError::InvalidOperation`] for unknown operation tag.
+    pub fn from_bytes(patch_data: &[u8]) -> Result<Self, PatchError> {
+        let mut reader = PatchReader {
+            data: patch_data,
+            pos: 0,
+        };
+
+        let mut magic = [0; PATCH_MAGIC.len()];
+        magic.copy_from_slice(reader.read_bytes(PATCH_MAGIC.len())?);
+
+        if magic != PATCH_MAGIC {
+            return Err(PatchError::InvalidHeader(magic));
+        }
+
+        let version = reader.read_u8()?;
+
+        if version != PATCH_VERSION {
+            return Err(PatchError::UnsupportedVersion(version));
+        }
+
+        let source_hash = reader.read_u64()?;
+        let target_hash = reader.read_u64()?;
+        let op_count = reader.read_size()?;
+
+        // Every operation takes at least two bytes
+        let mut operations =
+            Vec::with_capacity(op_count.min(patch_data.len() / 2));
+
+        for _ in 0..op_count {
+            let offset = reader.pos;
+            let tag = reader.read_u8()?;
+            let path = reader.read_sized_bytes()?.to_vec();
+
+            operations.push(match tag {
+                OP_REMOVE => PatchOperation::Remove { path },
+                OP_ADD => PatchOperation::Add {
+                    path,
+                    data: reader.read_sized_bytes()?.to_vec(),
+                },
+                OP_CHANGE => PatchOperation::Change {
+                    path,
+                    delta: reader.read_sized_bytes()?.to_vec(),
+                },
+                _ => return Err(PatchError::InvalidOperation { tag, offset }),
+            });
+        }
+
+        Ok(Self {
+            source_hash,
+            target_hash,
+            operations,
+        })
+    }
+
+    /// Applies the patch to `archive`.
+    ///
+    /// Hash of `archive` is verified before patching, and hash of the result is verified after. Unchanged files keep their order, and added files are appended to the end. To minimize the binary diff with the original archive file, encrypt the result with [`Archive::to_bytes_with_layout`].
+    ///
+    /// # Errors
+    ///
+    /// - [`PatchError::SourceMismatch`] if `archive` is not the one, that the patch was made for. `archive` is left untouched.
+    /// - [`PatchError::MissingEntry`] or [`PatchError::InvalidDelta`] if the patch is corrupted. `archive` is left untouched.
+    /// - [`PatchError::TargetMismatch`] if the patch is corrupted, and the result is not the expected one. `archive` is left untouched.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{Archive, ArchiveLayout, Patch};
+    /// use std::fs::{read, write};
+    ///
+    /// let data = read("C:/Game/Game.rgss3a").unwrap();
+    /// let patch = Patch::from_bytes(&read("translation.rgsspatch").unwrap()).unwrap();
+    ///
+    /// let layout = ArchiveLayout::new(&data).unwrap();
+    /// let mut archive = Archive::open(&data).unwrap();
+    /// patch.apply(&mut archive).unwrap();
+    ///
+    /// write("C:/Game/Game.rgss3a", archive.to_bytes_with_layout(&layout)).unwrap();
+    /// ```
+    pub fn apply(&self, archive: &mut Archive) -> Result<(), PatchError> {
+        let source_hash = archive.content_hash();
+
+        if source_hash != self.source_hash {
+            return Err(PatchError::SourceMismatch {
+                expected: self.source_hash,
+                actual: source_hash,
+            });
+        }
+
+        // All deltas are applied before modifying the archive, so corrupted patch leaves it untouched
+        let mut changed_data = Vec::new();
+
+        for operation in &self.operations {
+            match operation {
+                PatchOperation::Remove { path } => {
+                    if !archive.contains(path) {
+                        return Err(PatchError::MissingEntry {
+                            path: path.clone(),
+                        });
+                    }
+                }
+                PatchOperation::Change { path, delta } => {
+                    let Some(file) = archive.get(path) else {
+                        return Err(PatchError::MissingEntry {
+                            path: path.clone(),
+                        });
+                    };
+
+                    let data =
+                        apply_delta(&file.data, delta).ok_or_else(|| {
+                            PatchError::InvalidDelta { path: path.clone() }
+                        })?;
+                    changed_data.push(data);
+                }
+                PatchOperation::Add { .. } => {}
+            }
+        }
+
+        // Result is staged as paths and data, borrowed from the archive and the patch, and only written to the archive once its hash matches
+        let mut staged = archive
+            .files()
+            .iter()
+            .map(|file| (file.path.as_ref(), file.data.as_ref()))
+            .collect::<BTreeMap<_, _>>();
+        let mut staged_data = changed_data.iter();
+
+        for operation in &self.operations {
+            match operation {
+                PatchOperation::Remove { path } => {
+                    staged.remove(path.as_slice());
+                }
+                PatchOperation::Add { path, data } => {
+                    staged.insert(path, data);
+                }
+                PatchOperation::Change { path, .. } => {
+                    if let Some(data) = staged_data.next() {
+                        staged.insert(path, data);
+                    }
+                }
+            }
+        }
+
+        let target_hash = sorted_content_hash(staged);
+
+        if target_hash != self.target_hash {
+            return Err(PatchError::TargetMismatch {
+                expected: self.target_hash,
+                actual: target_hash,
+            });
+        }
+
+        let mut changed_data = changed_data.into_iter();
+
+        for operation in &self.operations {
+            match operation {
+                PatchOperation::Remove { path } => {
+                    archive.remove(path);
+                }
+                PatchOperation::Add { path, data } => {
+                    archive.insert(path.clone(), data.clone());
+                }
+                PatchOperation::Change { path, .. } => {
+                    if let Some(data) = changed_data.next() {
+                        archive.replace(path, data);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+#[cfg(feature = "std")]
+use crate::{ArchiveReader, ReadError};
+use crate::{
+    Engine, ExtractError,
+    key::DATA_EXT_LEN,
+    keystream::jump_key,
+    scan::{RawEntry, Scanner, xor_path},
+};
+#[cfg(feature = "std")]
+use std::{fs::File, io::BufReader, path::Path};
+
+/// Summary of an archive, returned from [`probe`].
+///
+/// # Fields
+/// - `engine` - Engine of the archive. See [`Engine`] for the notes about XP and VX archives.
+/// - `entry_count` - Count of entries in the archive.
+/// - `data_size` - Total size of data of all entries in bytes.
+/// - `base_key` - Key from the archive header, which metadata key is derived from. XP/VX archives don't store it, and always use `0xDEADCAFE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveInfo {
+    pub engine: Engine,
+    pub entry_count: usize,
+    pub data_size: u64,
+    pub base_key: u32,
+}
+
+/// Returns XP or VX engine, if the entry is a data file of one of them.
+pub(crate) fn detect_older(
+    archive_data: &[u8],
+    engine: Engine,
+    entry: &RawEntry,
+) -> Option<Engine> {
+    let path_data = &archive_data[entry.path.clone()];
+    let skipped = path_data.len().saturating_sub(DATA_EXT_LEN);
+    let key = jump_key(entry.path_key, skipped as u64);
+
+    // Only the end of the path is decrypted, to avoid allocations
+    let mut path_end = [0; DATA_EXT_LEN];
+    let path_end = &mut path_end[..path_data.len() - skipped];
+    path_end.copy_from_slice(&path_data[skipped..]);
+    xor_path(engine, key, path_end);
+
+    engine.detect_older(path_end)
+}
+
+/// Reads archive metadata without decrypting entry data and without modifying `archive_data`.
+///
+/// XP and VX archives are told apart by the extensions of data files among the entries. See [`Engine`].
+///
+/// # Parameters
+/// - `archive_data`: The content of the archive file.
+///
+/// # Errors
+///
+/// Same as [`crate::ArchiveIndex::new`].
+///
+/// # Example
+/// ```no_run
+/// use rpgmad_lib::probe;
+/// use std::fs::read;
+///
+/// let data = read("C:/Game/Game.rgss2a").unwrap();
+/// let info = probe(&data).unwrap();
+///
+/// println!("{}: {} files, {} bytes", info.engine, info.entry_count, info.data_size);
+/// ```
+pub fn probe(archive_data: &[u8]) -> Result<ArchiveInfo, ExtractError> {
+    let mut scanner = Scanner::new(archive_data)?;
+
+    let mut info = ArchiveInfo {
+        engine: scanner.engine(),
+        entry_count: 0,
+        data_size: 0,
+        base_key: scanner.base_key(),
+    };
+
+    let mut engine_detected = info.engine.is_vx_ace();
+
+    for entry in &mut scanner {
+        let entry = entry?;
+
+        if !engine_detected {
+            if let Some(engine) =
+                detect_older(archive_data, info.engine, &entry)
+            {
+                info.engine = engine;
+                engine_detected = true;
+            }
+        }
+
+        info.entry_count += 1;
+        info.data_size += u64::from(entry.size);
+    }
+
+    Ok(info)
+}
+
+/// Reads metadata of the archive file, without reading or decrypting entry data.
+///
+/// Same as [`probe`], but only the metadata is read from the file, with [`ArchiveReader`]. If the archive has no data files, XP and VX archives are told apart by the file extension: `.rgssad` for XP and `.rgss2a` for VX.
+///
+/// # Errors
+///
+/// - [`ReadError::Io`] if opening or reading the file fails.
+/// - [`ReadError::Extract`] with the same errors as [`probe`].
+///
+/// # Example
+/// ```no_run
+/// use rpgmad_lib::{probe_file, Engine};
+///
+/// let info = probe_file("C:/Game/Game.rgss2a").unwrap();
+/// assert_eq!(info.engine, Engine::VX);
+/// ```
+#[cfg(feature = "std")]
+pub fn probe_file(path: impl AsRef<Path>) -> Result<ArchiveInfo, ReadError> {
+    let path = path.as_ref();
+    let mut reader = ArchiveReader::new(BufReader::new(File::open(path)?))?;
+
+    let mut info = ArchiveInfo {
+        engine: reader.engine(),
+        entry_count: 0,
+        data_size: 0,
+        base_key: reader.base_key(),
+    };
+
+    while let Some(entry) = reader.next_entry()? {
+        info.entry_count += 1;
+        info.data_size += u64::from(entry.size);
+    }
+
+    info.engine = reader.engine();
+
+    if !info.engine.is_vx_ace() && !reader.engine_detected() {
+        if let Some(engine) = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(Engine::from_file_name)
+            .filter(Engine::is_older)
+        {
+            info.engine = engine;
+        }
+    }
+
+    Ok(info)
+}
@@ -0,0 +1,379 @@
+use crate::{
+    DecryptOptions, Engine, EntryInfo, ExtractError, KeyStream,
+    scan::{MetadataParser, MetadataSource, xor_path},
+};
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    iter, mem,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Extract(#[from] ExtractError),
+}
+
+/// [`MetadataSource`] over a [`Read`] + [`Seek`] stream.
+struct StreamSource<R> {
+    reader: R,
+    // Stream position of the archive start
+    start: u64,
+    // Positions below are relative to `start`
+    len: u64,
+    pos: u64,
+    // Encrypted path of the last parsed entry
+    path: Vec<u8>,
+}
+
+impl<R: Read> MetadataSource for StreamSource<R> {
+    type Error = ReadError;
+
+    #[inline]
+    fn pos(&self) -> u64 {
+        self.pos
+    }
+
+    #[inline]
+    fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), ReadError> {
+        self.reader.read_exact(buf)?;
+        self.pos += buf.len() as u64;
+        Ok(())
+    }
+
+    fn read_path(&mut self, size: usize) -> Result<(), ReadError> {
+        self.path.clear();
+        self.path.resize(size, 0);
+        self.reader.read_exact(&mut self.path)?;
+        self.pos += size as u64;
+        Ok(())
+    }
+
+    #[inline]
+    fn skip(&mut self, count: u64) {
+        // Stream is seeked to `pos` before the next record is parsed
+        self.pos += count;
+    }
+}
+
+/// A struct responsible for decrypting archives from [`Read`] + [`Seek`] sources, without loading the whole archive into memory.
+///
+/// Entry metadata is parsed lazily, one entry at a time, and entry data is only read and decrypted when requested.
+pub struct ArchiveReader<R> {
+    source: StreamSource<R>,
+    parser: MetadataParser,
+    engine: Engine,
+
+    finished: bool,
+    engine_detected: bool,
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    /// Creates a new [`ArchiveReader`] and parses the archive header.
+    ///
+    /// # Parameters
+    /// - `reader`: Source of the archive data. Archive is expected to start at the current stream position.
+    ///
+    /// # Errors
+    ///
+    /// - [`ReadError::Io`] if reading from `reader` fails.
+    /// - [`ExtractError::InvalidHeader`] for invalid header.
+    /// - [`ExtractError::InvalidEngine`] for invalid header engine type byte.
+    /// - [`ExtractError::UnexpectedEof`] if archive is too short to contain the header.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::ArchiveReader;
+    /// use std::{fs::{File, create_dir_all}, io::{BufReader, copy}, path::PathBuf};
+    ///
+    /// let file = BufReader::new(File::open("C:/Game/Game.rgss3a").unwrap());
+    /// let mut reader = ArchiveReader::new(file).unwrap();
+    ///
+    /// while let Some(entry) = reader.next_entry().unwrap() {
+    ///     let path = String::from_utf8_lossy(&entry.path);
+    ///     let output_path = PathBuf::from("C:/Game").join(path.as_ref());
+    ///
+    ///     if let Some(parent) = output_path.parent() {
+    ///         create_dir_all(parent).unwrap();
+    ///     }
+    ///
+    ///     let mut output_file = File::create(output_path).unwrap();
+    ///     copy(&mut reader.entry_reader(&entry).unwrap(), &mut output_file).unwrap();
+    /// }
+    /// ```
+    pub fn new(reader: R) -> Result<Self, ReadError> {
+        Self::with_options(reader, &DecryptOptions::default())
+    }
+
+    /// Same as [`ArchiveReader::new`], but derives keys according to `options`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`ArchiveReader::new`].
+    pub fn with_options(
+        mut reader: R,
+        options: &DecryptOptions,
+    ) -> Result<Self, ReadError> {
+        let start = reader.stream_position()?;
+        let len = reader.seek(SeekFrom::End(0))?.saturating_sub(start);
+        reader.seek(SeekFrom::Start(start))?;
+
+        let mut source = StreamSource {
+            reader,
+            start,
+            len,
+            pos: 0,
+            path: Vec::new(),
+        };
+        let parser = MetadataParser::new(&mut source, options)?;
+
+        Ok(Self {
+            source,
+            parser,
+            engine: parser.engine(),
+
+            finished: false,
+            engine_detected: false,
+        })
+    }
+
+    /// Returns the engine of the archive.
+    ///
+    /// XP and VX archives are told apart by the extensions of data files among the entries, see [`Engine`]. Since entries are parsed lazily, XP/VX archives are reported as [`Engine::XP`] until the first data file is parsed.
+    #[must_use]
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Returns the key from the archive header, see [`crate::ArchiveInfo`].
+    pub(crate) fn base_key(&self) -> u32 {
+        self.parser.base_key()
+    }
+
+    /// Returns whether XP/VX engine was detected from a data file, or [`ArchiveReader::engine`] is still the [`Engine::XP`] default.
+    pub(crate) fn engine_detected(&self) -> bool {
+        self.engine_detected
+    }
+
+    /// Consumes the [`ArchiveReader`], returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.source.reader
+    }
+
+    /// Parses metadata of the next entry in the archive, without reading its data.
+    ///
+    /// # Returns
+    /// - [`EntryInfo`] of the next entry.
+    /// - [`None`], if there's no entries left. After an error, [`None`] is always returned.
+    ///
+    /// # Errors
+    ///
+    /// - [`ReadError::Io`] if reading from the underlying reader fails.
+    /// - [`ExtractError::UnexpectedEof`] if entry metadata is truncated.
+    /// - [`ExtractError::PathTooLong`] if entry path exceeds the remaining archive data.
+    /// - [`ExtractError::EntryOutOfBounds`] if entry data lies outside of the archive.
+    pub fn next_entry(&mut self) -> Result<Option<EntryInfo>, ReadError> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let result = self
+            .source
+            .reader
+            .seek(SeekFrom::Start(self.source.start + self.source.pos))
+            .map_err(ReadError::from)
+            .and_then(|_| self.parser.next_entry(&mut self.source))
+            .map(|entry| {
+                entry.map(|entry| {
+                    let mut path = mem::take(&mut self.source.path);
+                    xor_path(self.engine, entry.path_key, &mut path);
+
+                    EntryInfo {
+                        path,
+                        offset: entry.offset,
+                        size: entry.size,
+                        key: entry.key,
+                    }
+                })
+            });
+
+        match &result {
+            Ok(Some(entry)) => {
+                if !self.engine_detected {
+                    if let Some(engine) = self.engine.detect_older(&entry.path)
+                    {
+                        self.engine = engine;
+                        self.engine_detected = true;
+                    }
+                }
+            }
+            _ => self.finished = true,
+        }
+
+        result
+    }
+
+    /// Returns an iterator over metadata of the remaining archive entries.
+    ///
+    /// See [`ArchiveReader::next_entry`].
+    pub fn entries(
+        &mut self,
+    ) -> impl Iterator<Item = Result<EntryInfo, ReadError>> + '_ {
+        iter::from_fn(|| self.next_entry().transpose())
+    }
+
+    /// Returns a [`Read`] + [`Seek`] adapter, that reads and decrypts data of `entry` on the fly.
+    ///
+    /// Seeking inside the entry doesn't read or decrypt the skipped data, see [`KeyStream::seek`].
+    ///
+    /// # Parameters
+    /// - `entry`: Entry, previously returned from this reader.
+    ///
+    /// # Errors
+    ///
+    /// - [`ReadError::Io`] if seeking to the entry data fails.
+    pub fn entry_reader(
+        &mut self,
+        entry: &EntryInfo,
+    ) -> Result<EntryReader<'_, R>, ReadError> {
+        let start = self.source.start + entry.offset;
+        self.source.reader.seek(SeekFrom::Start(start))?;
+
+        Ok(EntryReader {
+            reader: &mut self.source.reader,
+            start,
+            size: u64::from(entry.size),
+            keystream: KeyStream::new(entry.key),
+        })
+    }
+
+    /// Reads and decrypts data of `entry`, appending it to `buf`.
+    ///
+    /// # Parameters
+    /// - `entry`: Entry, previously returned from this reader.
+    /// - `buf`: Buffer to append decrypted data to.
+    ///
+    /// # Returns
+    /// - Number of bytes appended to `buf`.
+    ///
+    /// # Errors
+    ///
+    /// - [`ReadError::Io`] if reading from the underlying reader fails.
+    pub fn read_entry(
+        &mut self,
+        entry: &EntryInfo,
+        buf: &mut Vec<u8>,
+    ) -> Result<usize, ReadError> {
+        Ok(self.entry_reader(entry)?.read_to_end(buf)?)
+    }
+
+    /// Reads and decrypts `len` bytes of `entry` data, starting at `offset` from the start of the entry, without reading the preceding data.
+    ///
+    /// # Parameters
+    /// - `entry`: Entry, previously returned from this reader.
+    /// - `offset`: Offset of the range from the start of the entry data.
+    /// - `len`: Size of the range in bytes. Range, that exceeds the entry, is truncated at the end of the entry.
+    ///
+    /// # Returns
+    /// - Decrypted data of the range.
+    ///
+    /// # Errors
+    ///
+    /// - [`ReadError::Io`] if reading from the underlying reader fails.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::ArchiveReader;
+    /// use std::{fs::File, io::BufReader};
+    ///
+    /// let file = BufReader::new(File::open("C:/Game/Game.rgss3a").unwrap());
+    /// let mut reader = ArchiveReader::new(file).unwrap();
+    ///
+    /// while let Some(entry) = reader.next_entry().unwrap() {
+    ///     if entry.path == b"Audio\\BGM\\Theme1.ogg" {
+    ///         // Skip the first megabyte of the track.
+    ///         let chunk = reader.read_entry_range(&entry, 1 << 20, 64 << 10).unwrap();
+    ///     }
+    /// }
+    /// ```
+    pub fn read_entry_range(
+        &mut self,
+        entry: &EntryInfo,
+        offset: u64,
+        len: usize,
+    ) -> Result<Vec<u8>, ReadError> {
+        let mut entry_reader = self.entry_reader(entry)?;
+        entry_reader.seek(SeekFrom::Start(offset))?;
+
+        let mut buf = Vec::new();
+        entry_reader.take(len as u64).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// A [`Read`] + [`Seek`] adapter over a single archive entry, returned by [`ArchiveReader::entry_reader`].
+///
+/// Reads decrypted data of the entry. Positions are relative to the start of the entry data, and seeking past its end is allowed, like with files, but nothing is read there.
+pub struct EntryReader<'r, R> {
+    reader: &'r mut R,
+    start: u64,
+    size: u64,
+    keystream: KeyStream,
+}
+
+impl<R> EntryReader<'_, R> {
+    /// Returns the count of entry bytes, that are not read yet.
+    #[must_use]
+    pub fn remaining(&self) -> u64 {
+        self.size.saturating_sub(self.keystream.position())
+    }
+}
+
+impl<R: Read> Read for EntryReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining()).unwrap_or(usize::MAX));
+
+        let read = self.reader.read(&mut buf[..max])?;
+
+        if read == 0 && max != 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        self.keystream.apply_keystream(&mut buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for EntryReader<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => {
+                self.keystream.position().checked_add_signed(offset)
+            }
+        };
+
+        let (Some(new_pos), Some(stream_pos)) =
+            (new_pos, new_pos.and_then(|pos| self.start.checked_add(pos)))
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ));
+        };
+
+        self.reader.seek(SeekFrom::Start(stream_pos))?;
+        self.keystream.seek(new_pos);
+        Ok(new_pos)
+    }
+}
@@ -0,0 +1,442 @@
+use crate::{
+    ARCHIVE_HEADER, DecryptOptions, Engine, ExtractError, OLDER_DECRYPTION_KEY,
+    keystream::jump_key, next_key_older,
+};
+use core::ops::Range;
+
+/// Location of a single entry in the archive, with the keys required to decrypt its path and data.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "alloc"), allow(dead_code))]
+pub(crate) struct RawEntry {
+    pub path: Range<usize>,
+    pub path_key: u32,
+    pub offset: u64,
+    pub size: u32,
+    pub key: u32,
+    /// Entry key, as stored in the archive. Same as `key` for XP/VX archives.
+    pub stored_key: u32,
+}
+
+#[cfg_attr(not(feature = "alloc"), allow(dead_code))]
+impl RawEntry {
+    /// Decrypts path if `path_data` is encrypted, encrypts path if `path_data` is decrypted.
+    ///
+    /// `path_data` must be the full path, starting from its first byte.
+    pub fn xor_path(&self, engine: Engine, path_data: &mut [u8]) {
+        xor_path(engine, self.path_key, path_data);
+    }
+}
+
+#[inline]
+pub(crate) fn xor_path(engine: Engine, mut key: u32, path_data: &mut [u8]) {
+    if engine.is_vx_ace() {
+        let key_bytes = key.to_le_bytes();
+
+        for (idx, byte) in path_data.iter_mut().enumerate() {
+            *byte ^= key_bytes[idx % 4];
+        }
+    } else {
+        for byte in path_data {
+            *byte ^= key as u8;
+            key = next_key_older(key);
+        }
+    }
+}
+
+/// Source of archive bytes, that [`MetadataParser`] reads metadata from.
+///
+/// Positions are relative to the archive start. Parser checks bounds against [`MetadataSource::len`] before every read, so sources only report their own failures, like I/O errors.
+pub(crate) trait MetadataSource {
+    type Error: From<ExtractError>;
+
+    /// Returns the position of the next byte to read.
+    fn pos(&self) -> u64;
+
+    /// Returns the size of the archive.
+    fn len(&self) -> u64;
+
+    /// Reads exactly `buf.len()` bytes.
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Reads `size` bytes of the encrypted entry path.
+    fn read_path(&mut self, size: usize) -> Result<(), Self::Error>;
+
+    /// Skips `count` bytes of entry data.
+    fn skip(&mut self, count: u64);
+}
+
+/// [`MetadataSource`] over the archive, or its part, in memory.
+pub(crate) struct SliceSource<'a> {
+    data: &'a [u8],
+    /// Position of `data` start in the archive.
+    base: usize,
+    pos: usize,
+    len: usize,
+}
+
+impl<'a> SliceSource<'a> {
+    /// Creates the source over the whole archive.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            base: 0,
+            pos: 0,
+            len: data.len(),
+        }
+    }
+
+    /// Creates the source over `data`, which starts at `base` of the archive of `len` bytes.
+    ///
+    /// Records, that are parsed from the source, must lie inside `data`.
+    pub fn with_base(data: &'a [u8], base: usize, len: usize) -> Self {
+        Self {
+            data,
+            base,
+            pos: base,
+            len,
+        }
+    }
+}
+
+impl MetadataSource for SliceSource<'_> {
+    type Error = ExtractError;
+
+    #[inline]
+    fn pos(&self) -> u64 {
+        self.pos as u64
+    }
+
+    #[inline]
+    fn len(&self) -> u64 {
+        self.len as u64
+    }
+
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), ExtractError> {
+        let start = self.pos - self.base;
+        buf.copy_from_slice(&self.data[start..start + buf.len()]);
+        self.pos += buf.len();
+        Ok(())
+    }
+
+    #[inline]
+    fn read_path(&mut self, size: usize) -> Result<(), ExtractError> {
+        self.pos += size;
+        Ok(())
+    }
+
+    #[inline]
+    fn skip(&mut self, count: u64) {
+        self.pos += count as usize;
+    }
+}
+
+/// Parses archive header and entry metadata records from a [`MetadataSource`].
+///
+/// This is the only place, where metadata layout and key derivation are implemented: [`Scanner`], [`crate::ArchiveReader`] and [`crate::DecryptedEntries`] all parse records with it.
+#[derive(Clone, Copy)]
+pub(crate) struct MetadataParser {
+    options: DecryptOptions,
+    engine: Engine,
+    base_key: u32,
+    key: u32,
+    index: usize,
+}
+
+impl MetadataParser {
+    /// Parses the archive header from `source`.
+    pub fn new<S: MetadataSource>(
+        source: &mut S,
+        options: &DecryptOptions,
+    ) -> Result<Self, S::Error> {
+        let mut parser = Self {
+            options: *options,
+            engine: Engine::XP,
+            base_key: options.base_key.unwrap_or(OLDER_DECRYPTION_KEY),
+            key: options.base_key.unwrap_or(OLDER_DECRYPTION_KEY),
+            index: 0,
+        };
+
+        let mut header = [0; ARCHIVE_HEADER.len()];
+        Self::read_exact(source, &mut header)?;
+
+        if &header != ARCHIVE_HEADER {
+            return Err(ExtractError::InvalidHeader(header).into());
+        }
+
+        let mut engine_type = [0; 1];
+        Self::read_exact(source, &mut engine_type)?;
+
+        parser.engine = Engine::from_version(engine_type[0])
+            .ok_or(ExtractError::InvalidEngine(engine_type[0]))?;
+
+        if parser.engine.is_vx_ace() {
+            let stored_key = Self::read_u32(source)?;
+            parser.base_key = options.base_key.unwrap_or(stored_key);
+            parser.key = (options.derive_key)(parser.base_key);
+        }
+
+        Ok(parser)
+    }
+
+    #[inline]
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    #[inline]
+    pub fn base_key(&self) -> u32 {
+        self.base_key
+    }
+
+    /// Returns the key, that the next record is decrypted with. For VX Ace archives, it's the metadata key.
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn key(&self) -> u32 {
+        self.key
+    }
+
+    /// Returns the parser state before the `index`th record, which is decrypted with `key`, returned from [`MetadataParser::key`] before that record was parsed.
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn at(mut self, index: usize, key: u32) -> Self {
+        self.index = index;
+        self.key = key;
+        self
+    }
+
+    #[inline]
+    fn read_exact<S: MetadataSource>(
+        source: &mut S,
+        buf: &mut [u8],
+    ) -> Result<(), S::Error> {
+        let remaining = source.len().saturating_sub(source.pos());
+
+        if buf.len() as u64 > remaining {
+            return Err(ExtractError::UnexpectedEof {
+                offset: source.pos(),
+                needed: (buf.len() as u64 - remaining) as usize,
+            }
+            .into());
+        }
+
+        source.read(buf)
+    }
+
+    #[inline]
+    fn read_u32<S: MetadataSource>(source: &mut S) -> Result<u32, S::Error> {
+        let mut buf = [0; 4];
+        Self::read_exact(source, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    #[inline]
+    fn read_u32_older<S: MetadataSource>(
+        &mut self,
+        source: &mut S,
+    ) -> Result<u32, S::Error> {
+        let decrypted = Self::read_u32(source)? ^ self.key;
+        self.key = next_key_older(self.key);
+        Ok(decrypted)
+    }
+
+    #[inline]
+    fn read_path<S: MetadataSource>(
+        &self,
+        source: &mut S,
+        path_size: usize,
+    ) -> Result<Range<usize>, S::Error> {
+        if path_size as u64 > source.len().saturating_sub(source.pos()) {
+            return Err(ExtractError::PathTooLong {
+                index: self.index,
+                size: path_size,
+            }
+            .into());
+        }
+
+        let start = source.pos() as usize;
+        source.read_path(path_size)?;
+        Ok(start..start + path_size)
+    }
+
+    #[inline]
+    fn check_data_bounds(
+        &self,
+        len: u64,
+        offset: u64,
+        size: u32,
+    ) -> Result<(), ExtractError> {
+        if offset > len || u64::from(size) > len - offset {
+            return Err(ExtractError::EntryOutOfBounds {
+                index: self.index,
+                offset,
+                size: size as usize,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn next_vxace<S: MetadataSource>(
+        &mut self,
+        source: &mut S,
+    ) -> Result<Option<RawEntry>, S::Error> {
+        let offset = u64::from(Self::read_u32(source)? ^ self.key);
+
+        // End of data
+        if offset == 0 {
+            return Ok(None);
+        }
+
+        let size = Self::read_u32(source)? ^ self.key;
+        let stored_key = Self::read_u32(source)?;
+        let key = (self.options.entry_key)(stored_key, self.key);
+        let path_size = (Self::read_u32(source)? ^ self.key) as usize;
+        let path = self.read_path(source, path_size)?;
+
+        self.check_data_bounds(source.len(), offset, size)?;
+
+        Ok(Some(RawEntry {
+            path,
+            path_key: self.key,
+            offset,
+            size,
+            key,
+            stored_key,
+        }))
+    }
+
+    fn next_older<S: MetadataSource>(
+        &mut self,
+        source: &mut S,
+    ) -> Result<Option<RawEntry>, S::Error> {
+        // End of data
+        if source.pos() == source.len() {
+            return Ok(None);
+        }
+
+        let path_size = self.read_u32_older(source)? as usize;
+        let path_key = self.key;
+        let path = self.read_path(source, path_size)?;
+
+        self.key = jump_key(self.key, path_size as u64);
+
+        let size = self.read_u32_older(source)?;
+        let offset = source.pos();
+
+        self.check_data_bounds(source.len(), offset, size)?;
+
+        // Skip data block
+        source.skip(u64::from(size));
+
+        Ok(Some(RawEntry {
+            path,
+            path_key,
+            offset,
+            size,
+            key: self.key,
+            stored_key: self.key,
+        }))
+    }
+
+    /// Parses the next metadata record from `source`, and validates, that its path and data lie inside the archive.
+    ///
+    /// # Returns
+    /// - [`RawEntry`] of the next entry.
+    /// - [`None`] at the end of metadata.
+    pub fn next_entry<S: MetadataSource>(
+        &mut self,
+        source: &mut S,
+    ) -> Result<Option<RawEntry>, S::Error> {
+        let result = if self.engine.is_vx_ace() {
+            self.next_vxace(source)
+        } else {
+            self.next_older(source)
+        };
+
+        self.index += 1;
+        result
+    }
+}
+
+/// Walks over archive metadata without modifying the archive.
+///
+/// Yields [`RawEntry`] for every entry in the archive, verifying that its path and data lie inside the archive.
+pub(crate) struct Scanner<'a> {
+    source: SliceSource<'a>,
+    parser: MetadataParser,
+    finished: bool,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, ExtractError> {
+        Self::with_options(data, &DecryptOptions::default())
+    }
+
+    pub fn with_options(
+        data: &'a [u8],
+        options: &DecryptOptions,
+    ) -> Result<Self, ExtractError> {
+        let mut source = SliceSource::new(data);
+        let parser = MetadataParser::new(&mut source, options)?;
+
+        Ok(Self {
+            source,
+            parser,
+            finished: false,
+        })
+    }
+
+    #[inline]
+    pub fn engine(&self) -> Engine {
+        self.parser.engine()
+    }
+
+    #[inline]
+    pub fn base_key(&self) -> u32 {
+        self.parser.base_key()
+    }
+
+    /// Returns the key, that metadata is currently decrypted with. For VX Ace archives, it's the metadata key.
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn key(&self) -> u32 {
+        self.parser.key()
+    }
+
+    /// Returns the parser state before the next record.
+    #[inline]
+    pub fn parser(&self) -> MetadataParser {
+        self.parser
+    }
+
+    /// Returns the position right after the last parsed metadata.
+    #[inline]
+    pub fn pos(&self) -> usize {
+        self.source.pos
+    }
+}
+
+impl Iterator for Scanner<'_> {
+    type Item = Result<RawEntry, ExtractError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let result = self.parser.next_entry(&mut self.source);
+
+        match result {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(err) => {
+                self.finished = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use crate::{
+    DecryptOptions, Decrypter, Engine, ExtractError, KeyStream,
+    keystream::jump_key,
+    probe::detect_older,
+    scan::{RawEntry, Scanner, xor_path},
+};
+
+/// Size of the stack buffer, that paths are decrypted into when looking up entries by path.
+const PATH_CHUNK_SIZE: usize = 64;
+
+/// Location of an entry in the archive, with the keys required to decrypt its path and data.
+///
+/// Unlike [`crate::ArchiveEntry`], it doesn't borrow the archive, so it can be stored and passed around freely. Path and data are decrypted with [`ArchiveView::decrypt_path_into`] and [`ArchiveView::decrypt_entry_into`].
+///
+/// # Fields
+/// - `index` - Index of the entry in the archive.
+/// - `path_offset` - Offset of the entry's encrypted path from the start of the archive.
+/// - `path_size` - Size of the entry's path in bytes.
+/// - `path_key` - Key, which the entry's path is encrypted with.
+/// - `offset` - Offset of the entry's encrypted data from the start of the archive.
+/// - `size` - Size of the entry's data in bytes.
+/// - `key` - Key, which the entry's data is encrypted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryLocation {
+    pub index: usize,
+    pub path_offset: usize,
+    pub path_size: usize,
+    pub path_key: u32,
+    pub offset: u64,
+    pub size: u32,
+    pub key: u32,
+}
+
+impl EntryLocation {
+    fn from_raw(index: usize, raw_entry: &RawEntry) -> Self {
+        Self {
+            index,
+            path_offset: raw_entry.path.start,
+            path_size: raw_entry.path.len(),
+            path_key: raw_entry.path_key,
+            offset: raw_entry.offset,
+            size: raw_entry.size,
+            key: raw_entry.key,
+        }
+    }
+}
+
+/// Checks that `out` fits `needed` bytes, and returns its prefix of that size.
+#[inline]
+fn output_prefix(
+    out: &mut [u8],
+    needed: usize,
+) -> Result<&mut [u8], ExtractError> {
+    let available = out.len();
+
+    out.get_mut(..needed)
+        .ok_or(ExtractError::BufferTooSmall { needed, available })
+}
+
+/// Returns encrypted entry data from `archive_data`.
+///
+/// `index` is only used for errors.
+pub(crate) fn entry_data(
+    archive_data: &[u8],
+    index: usize,
+    offset: u64,
+    size: u32,
+) -> Result<&[u8], ExtractError> {
+    usize::try_from(offset)
+        .ok()
+        .and_then(|start| {
+            archive_data.get(start..start.checked_add(size as usize)?)
+        })
+        .ok_or(ExtractError::EntryOutOfBounds {
+            index,
+            offset,
+            size: size as usize,
+        })
+}
+
+/// Decrypts entry data from `archive_data` into the beginning of `out`, leaving `archive_data` untouched.
+///
+/// `index` is only used for errors.
+pub(crate) fn decrypt_data_into<'o>(
+    archive_data: &[u8],
+    index: usize,
+    offset: u64,
+    size: u32,
+    key: u32,
+    out: &'o mut [u8],
+) -> Result<&'o mut [u8], ExtractError> {
+    let data = entry_data(archive_data, index, offset, size)?;
+    let out = output_prefix(out, data.len())?;
+    out.copy_from_slice(data);
+    Decrypter::xor_data(key, out);
+    Ok(out)
+}
+
+/// Decrypts the range of entry data, starting at `range_offset` from the start of the entry, from `archive_data` into the beginning of `out`. The range is as long as `out`, and is truncated at the end of the entry.
+///
+/// `index` is only used for errors.
+fn decrypt_data_range_into<'o>(
+    archive_data: &[u8],
+    index: usize,
+    offset: u64,
+    size: u32,
+    key: u32,
+    range_offset: u64,
+    out: &'o mut [u8],
+) -> Result<&'o mut [u8], ExtractError> {
+    let data = entry_data(archive_data, index, offset, size)?;
+    let range_start = usize::try_from(range_offset)
+        .map_or(data.len(), |start| start.min(data.len()));
+    let range = &data[range_start..];
+
+    let out_len = out.len().min(range.len());
+    let out = &mut out[..out_len];
+    out.copy_from_slice(&range[..out_len]);
+
+    let mut keystream = KeyStream::new(key);
+    keystream.seek(range_offset);
+    keystream.apply_keystream(out);
+    Ok(out)
+}
+
+/// Read-only view of the archive, that decrypts entries into caller-provided buffers.
+///
+/// Unlike [`Decrypter::decrypt`], view never modifies the archive, so it works with read-only and shared buffers, and the same archive can be decrypted any number of times. It doesn't allocate, and is available without `std` and `alloc` features.
+#[derive(Clone, Copy)]
+pub struct ArchiveView<'a> {
+    data: &'a [u8],
+    options: DecryptOptions,
+    engine: Engine,
+    len: usize,
+}
+
+impl<'a> ArchiveView<'a> {
+    /// Creates the [`ArchiveView`] of archive content, validating its metadata.
+    ///
+    /// # Parameters
+    /// - `archive_data`: The content of the archive file. This data is not modified.
+    ///
+    /// # Errors
+    ///
+    /// - [`ExtractError::InvalidHeader`] for invalid header.
+    /// - [`ExtractError::InvalidEngine`] for invalid header engine type byte.
+    /// - [`ExtractError::UnexpectedEof`] if archive header or entry metadata is truncated.
+    /// - [`ExtractError::PathTooLong`] if entry path exceeds the remaining archive data.
+    /// - [`ExtractError::EntryOutOfBounds`] if entry data lies outside of the archive.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::ArchiveView;
+    /// use std::fs::read;
+    ///
+    /// let data = read("C:/Game/Game.rgss3a").unwrap();
+    /// let view = ArchiveView::new(&data).unwrap();
+    ///
+    /// let mut path = [0; 256];
+    /// let mut buffer = vec![0; 1 << 20];
+    ///
+    /// for entry in view.entries() {
+    ///     let path = view.decrypt_path_into(&entry, &mut path).unwrap();
+    ///     println!("{}", String::from_utf8_lossy(path));
+    ///
+    ///     if entry.size as usize <= buffer.len() {
+    ///         let data = view.decrypt_entry_into(&entry, &mut buffer).unwrap();
+    ///     }
+    /// }
+    /// ```
+    pub fn new(archive_data: &'a [u8]) -> Result<Self, ExtractError> {
+        Self::with_options(archive_data, &DecryptOptions::default())
+    }
+
+    /// Creates the [`ArchiveView`] of archive content, which metadata was already validated by [`ArchiveView::with_options`] with the same `options`.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn from_parts(
+        archive_data: &'a [u8],
+        options: DecryptOptions,
+        engine: Engine,
+        len: usize,
+    ) -> Self {
+        Self {
+            data: archive_data,
+            options,
+            engine,
+            len,
+        }
+    }
+
+    /// Same as [`ArchiveView::new`], but derives keys according to `options`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`ArchiveView::new`].
+    pub fn with_options(
+        archive_data: &'a [u8],
+        options: &DecryptOptions,
+    ) -> Result<Self, ExtractError> {
+        let mut scanner = Scanner::with_options(archive_data, options)?;
+        let mut engine = scanner.engine();
+        let mut engine_detected = engine.is_vx_ace();
+        let mut len = 0;
+
+        for raw_entry in &mut scanner {
+            let raw_entry = raw_entry?;
+
+            if !engine_detected {
+                if let Some(older_engine) =
+                    detect_older(archive_data, engine, &raw_entry)
+                {
+                    engine = older_engine;
+                    engine_detected = true;
+                }
+            }
+
+            len += 1;
+        }
+
+        Ok(Self {
+            data: archive_data,
+            options: *options,
+            engine,
+            len,
+        })
+    }
+
+    /// Returns the engine of the archive. XP and VX archives are told apart by the extensions of data files among the entries, see [`Engine`].
+    #[must_use]
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Returns the count of entries in the archive.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the archive has no entries.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns an iterator over locations of all archive entries, in the order they're stored in the archive.
+    ///
+    /// Only metadata is walked, so iteration is cheap even for very large archives.
+    #[must_use]
+    pub fn entries(&self) -> EntryLocations<'a> {
+        EntryLocations {
+            // Header was already validated in `with_options`
+            scanner: Scanner::with_options(self.data, &self.options).ok(),
+            index: 0,
+            len: self.len,
+        }
+    }
+
+    /// Returns location of the entry with the given `path`, decrypting paths on the stack.
+    ///
+    /// Paths are compared byte-by-byte. Note, that RPG Maker stores paths with backslash separators, e.g. `Data\System.rvdata2`.
+    #[must_use]
+    pub fn get(&self, path: &[u8]) -> Option<EntryLocation> {
+        self.entries().find(|entry| self.path_matches(entry, path))
+    }
+
+    fn path_matches(&self, entry: &EntryLocation, path: &[u8]) -> bool {
+        if entry.path_size != path.len() {
+            return false;
+        }
+
+        let Some(path_data) = self
+            .data
+            .get(entry.path_offset..entry.path_offset + entry.path_size)
+        else {
+            return false;
+        };
+
+        let mut key = entry.path_key;
+        let mut chunk = [0; PATH_CHUNK_SIZE];
+
+        for (encrypted, expected) in path_data
+            .chunks(PATH_CHUNK_SIZE)
+            .zip(path.chunks(PATH_CHUNK_SIZE))
+        {
+            let chunk = &mut chunk[..encrypted.len()];
+            chunk.copy_from_slice(encrypted);
+            xor_path(self.engine, key, chunk);
+
+            if chunk != expected {
+                return false;
+            }
+
+            // VX Ace key repeats every four bytes, and chunk size is a multiple of four
+            if self.engine.is_older() {
+                key = jump_key(key, PATH_CHUNK_SIZE as u64);
+            }
+        }
+
+        true
+    }
+
+    /// Decrypts path of `entry` into the beginning of `out`, leaving the archive untouched.
+    ///
+    /// # Parameters
+    /// - `entry`: Entry, returned from this view.
+    /// - `out`: Buffer to write decrypted path into. Must be at least `entry.path_size` bytes long.
+    ///
+    /// # Returns
+    /// - Prefix of `out`, that holds the decrypted path.
+    ///
+    /// # Errors
+    ///
+    /// - [`ExtractError::PathTooLong`] if `entry` doesn't belong to this archive and its path lies outside of it.
+    /// - [`ExtractError::BufferTooSmall`] if `out` is shorter than the path.
+    pub fn decrypt_path_into<'o>(
+        &self,
+        entry: &EntryLocation,
+        out: &'o mut [u8],
+    ) -> Result<&'o mut [u8], ExtractError> {
+        let path_data = entry
+            .path_offset
+            .checked_add(entry.path_size)
+            .and_then(|end| self.data.get(entry.path_offset..end))
+            .ok_or(ExtractError::PathTooLong {
+                index: entry.index,
+                size: entry.path_size,
+            })?;
+
+        let out = output_prefix(out, path_data.len())?;
+        out.copy_from_slice(path_data);
+        xor_path(self.engine, entry.path_key, out);
+        Ok(out)
+    }
+
+    /// Decrypts data of `entry` into the beginning of `out`, leaving the archive untouched.
+    ///
+    /// # Parameters
+    /// - `entry`: Entry, returned from this view.
+    /// - `out`: Buffer to write decrypted data into. Must be at least `entry.size` bytes long.
+    ///
+    /// # Returns
+    /// - Prefix of `out`, that holds the decrypted data.
+    ///
+    /// # Errors
+    ///
+    /// - [`ExtractError::EntryOutOfBounds`] if `entry` doesn't belong to this archive and its data lies outside of it.
+    /// - [`ExtractError::BufferTooSmall`] if `out` is shorter than the data.
+    pub fn decrypt_entry_into<'o>(
+        &self,
+        entry: &EntryLocation,
+        out: &'o mut [u8],
+    ) -> Result<&'o mut [u8], ExtractError> {
+        decrypt_data_into(
+            self.data,
+            entry.index,
+            entry.offset,
+            entry.size,
+            entry.key,
+            out,
+        )
+    }
+
+    /// Decrypts a byte range of `entry` data into the beginning of `out`, without decrypting the preceding data, leaving the archive untouched.
+    ///
+    /// # Parameters
+    /// - `entry`: Entry, returned from this view.
+    /// - `offset`: Offset of the range from the start of the entry data.
+    /// - `out`: Buffer to write decrypted range into. The range is as long as `out`, and is truncated at the end of the entry.
+    ///
+    /// # Returns
+    /// - Prefix of `out`, that holds the decrypted range.
+    ///
+    /// # Errors
+    ///
+    /// - [`ExtractError::EntryOutOfBounds`] if `entry` doesn't belong to this archive and its data lies outside of it.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::ArchiveView;
+    /// use std::fs::read;
+    ///
+    /// let data = read("C:/Game/Game.rgss3a").unwrap();
+    /// let view = ArchiveView::new(&data).unwrap();
+    /// let entry = view.get(b"Audio\\BGM\\Theme1.ogg").unwrap();
+    ///
+    /// // Decrypt 64 KiB from the middle of the track.
+    /// let mut chunk = [0; 64 << 10];
+    /// let chunk = view.decrypt_entry_range_into(&entry, u64::from(entry.size) / 2, &mut chunk).unwrap();
+    /// ```
+    pub fn decrypt_entry_range_into<'o>(
+        &self,
+        entry: &EntryLocation,
+        offset: u64,
+        out: &'o mut [u8],
+    ) -> Result<&'o mut [u8], ExtractError> {
+        decrypt_data_range_into(
+            self.data,
+            entry.index,
+            entry.offset,
+            entry.size,
+            entry.key,
+            offset,
+            out,
+        )
+    }
+}
+
+/// Iterator over locations of archive entries, returned from [`ArchiveView::entries`].
+pub struct EntryLocations<'a> {
+    scanner: Option<Scanner<'a>>,
+    index: usize,
+    len: usize,
+}
+
+impl Iterator for EntryLocations<'_> {
+    type Item = EntryLocation;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index == self.len {
+            return None;
+        }
+
+        // Metadata was already validated in `ArchiveView::with_options`
+        let raw_entry = self.scanner.as_mut()?.next()?.ok()?;
+        let entry = EntryLocation::from_raw(self.index, &raw_entry);
+
+        self.index += 1;
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.len - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for EntryLocations<'_> {}
@@ -0,0 +1,322 @@
+use crate::{
+    ARCHIVE_HEADER, Decrypter, EncryptOptions, Engine, OLDER_DECRYPTION_KEY,
+    keystream::jump_key, next_key_older, next_key_vxace, scan::xor_path,
+};
+use std::io::{self, Read, Write};
+use thiserror::Error;
+
+/// Size of the buffer, that entry data is copied through.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum WriteError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(
+        "Data of entry {index} has {actual} bytes, but {expected} bytes were declared."
+    )]
+    DataTooShort {
+        index: usize,
+        expected: u32,
+        actual: u32,
+    },
+    #[error("Data of entry {index} exceeds {expected} declared bytes.")]
+    DataTooLong { index: usize, expected: u32 },
+    #[error("All {0} declared entries are already written.")]
+    TooManyEntries(usize),
+    #[error("Only {written} of {expected} declared entries were written.")]
+    MissingEntries { written: usize, expected: usize },
+    #[error(
+        "Archive exceeds 4 GiB, which is the maximum size of VX Ace archive."
+    )]
+    ArchiveTooLarge,
+    #[error(
+        "A previous write failed and left a partially written entry, so the archive cannot be completed."
+    )]
+    Poisoned,
+}
+
+/// Struct representing metadata of a file, that will be written with [`ArchiveWriter`].
+///
+/// # Fields
+/// - `path` - Path to the file. See [`crate::ArchiveEntry`] for the notes about its encoding.
+/// - `size` - Exact size of the file's data in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryHeader<'a> {
+    pub path: &'a [u8],
+    pub size: u32,
+}
+
+/// A struct responsible for writing encrypted archives to [`Write`] destinations incrementally, without keeping the whole archive in memory.
+///
+/// VX Ace archives store metadata of all entries before their data, so paths and sizes of all entries must be declared upfront, when creating the writer. After that, entry data is written one entry at a time, in the declared order, from any [`Read`] source.
+///
+/// Produced archive is identical to the one produced by [`Decrypter::encrypt`], or [`Decrypter::encrypt_with_options`] with the same options.
+///
+/// Entry data is streamed, so its size can only be verified after a part of the entry is written. After any failed [`ArchiveWriter::write_entry`] call, the writer is poisoned, and all later calls, including [`ArchiveWriter::finish`], return [`WriteError::Poisoned`].
+pub struct ArchiveWriter<W: Write> {
+    writer: W,
+    engine: Engine,
+    key: u32,
+
+    // Path, size and data key of every entry
+    entries: Vec<(Vec<u8>, u32, u32)>,
+    index: usize,
+    buf: Vec<u8>,
+    poisoned: bool,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Creates a new [`ArchiveWriter`] and writes the archive header. For VX Ace archives, also writes metadata of all entries.
+    ///
+    /// # Parameters
+    /// - `writer`: Destination of the archive data.
+    /// - `engine`: Target archive engine.
+    /// - `entries`: Metadata of all entries, in the order their data will be written.
+    ///
+    /// # Errors
+    ///
+    /// - [`WriteError::Io`] if writing to `writer` fails.
+    /// - [`WriteError::ArchiveTooLarge`] if VX Ace entry data offsets don't fit into 4 GiB.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rpgmad_lib::{ArchiveWriter, EntryHeader, Engine};
+    /// use std::fs::{File, metadata};
+    /// use std::io::BufWriter;
+    ///
+    /// let paths = ["Audio/BGM/Theme1.ogg", "Audio/BGM/Theme2.ogg"];
+    /// let headers = paths.map(|path| EntryHeader {
+    ///     path: path.as_bytes(),
+    ///     size: metadata(path).unwrap().len() as u32,
+    /// });
+    ///
+    /// let output = BufWriter::new(File::create("Game.rgss3a").unwrap());
+    /// let mut writer = ArchiveWriter::new(output, Engine::VXAce, &headers).unwrap();
+    ///
+    /// for path in paths {
+    ///     writer.write_entry(File::open(path).unwrap()).unwrap();
+    /// }
+    ///
+    /// writer.finish().unwrap();
+    /// ```
+    pub fn new(
+        writer: W,
+        engine: Engine,
+        entries: &[EntryHeader],
+    ) -> Result<Self, WriteError> {
+        Self::with_options(
+            writer,
+            engine,
+            entries,
+            &mut EncryptOptions::default(),
+        )
+    }
+
+    /// Same as [`ArchiveWriter::new`], but encrypts VX Ace archives with the keys, chosen according to `options`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`ArchiveWriter::new`].
+    pub fn with_options(
+        writer: W,
+        engine: Engine,
+        entries: &[EntryHeader],
+        options: &mut EncryptOptions,
+    ) -> Result<Self, WriteError> {
+        let mut archive_writer = Self {
+            writer,
+            engine,
+            key: OLDER_DECRYPTION_KEY,
+
+            entries: entries
+                .iter()
+                .map(|entry| (entry.path.to_vec(), entry.size, 0))
+                .collect(),
+            index: 0,
+            buf: vec![0; CHUNK_SIZE],
+            poisoned: false,
+        };
+
+        archive_writer.writer.write_all(ARCHIVE_HEADER)?;
+        archive_writer.writer.write_all(&[engine.version()])?;
+
+        if engine.is_vx_ace() {
+            archive_writer.write_metadata_vxace(options)?;
+        }
+
+        Ok(archive_writer)
+    }
+
+    fn write_metadata_vxace(
+        &mut self,
+        options: &mut EncryptOptions,
+    ) -> Result<(), WriteError> {
+        self.writer.write_all(&options.base_key.to_le_bytes())?;
+        self.key = next_key_vxace(options.base_key);
+
+        // Header, base key, metadata of every entry and the stop entry
+        let metadata_size = self
+            .entries
+            .iter()
+            .map(|(path, _, _)| 16 + path.len() as u64)
+            .sum::<u64>()
+            + 12
+            + 16;
+
+        let mut data_offset = metadata_size;
+
+        let mut entry_key = self.key;
+
+        for (path, size, key) in &mut self.entries {
+            let size = *size;
+            entry_key = options.next_entry_key(self.key, entry_key);
+            *key = entry_key;
+
+            let offset = u32::try_from(data_offset)
+                .map_err(|_| WriteError::ArchiveTooLarge)?;
+
+            self.writer.write_all(&(offset ^ self.key).to_le_bytes())?;
+            self.writer.write_all(&(size ^ self.key).to_le_bytes())?;
+            self.writer.write_all(&(*key ^ self.key).to_le_bytes())?;
+            self.writer
+                .write_all(&(path.len() as u32 ^ self.key).to_le_bytes())?;
+
+            let mut encrypted_path = path.clone();
+            xor_path(self.engine, self.key, &mut encrypted_path);
+            self.writer.write_all(&encrypted_path)?;
+
+            data_offset += u64::from(size);
+        }
+
+        if data_offset > u64::from(u32::MAX) + 1 {
+            return Err(WriteError::ArchiveTooLarge);
+        }
+
+        // Stop entry: its offset is xor'd against itself into 0 when decrypting
+        for _ in 0..4 {
+            self.writer.write_all(&self.key.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn write_u32_older(&mut self, u32: u32) -> io::Result<()> {
+        self.writer.write_all(&(u32 ^ self.key).to_le_bytes())?;
+        self.key = next_key_older(self.key);
+        Ok(())
+    }
+
+    fn write_entry_header_older(&mut self) -> io::Result<()> {
+        let (path, size, _) = &self.entries[self.index];
+        let (mut path, size) = (path.clone(), *size);
+
+        self.write_u32_older(path.len() as u32)?;
+
+        xor_path(self.engine, self.key, &mut path);
+        self.writer.write_all(&path)?;
+        self.key = jump_key(self.key, path.len() as u64);
+
+        self.write_u32_older(size)
+    }
+
+    /// Reads data of the next declared entry from `data`, encrypts and writes it.
+    ///
+    /// Exactly the declared size of the entry is read from `data`.
+    ///
+    /// # Errors
+    ///
+    /// - [`WriteError::Io`] if reading from `data` or writing to the destination fails.
+    /// - [`WriteError::TooManyEntries`] if data of all declared entries is already written.
+    /// - [`WriteError::DataTooShort`] if `data` ends before the declared size.
+    /// - [`WriteError::DataTooLong`] if `data` contains more bytes than the declared size.
+    /// - [`WriteError::Poisoned`] if a previous call failed.
+    pub fn write_entry(&mut self, data: impl Read) -> Result<(), WriteError> {
+        if self.poisoned {
+            return Err(WriteError::Poisoned);
+        }
+
+        // Nothing is written yet, so the writer stays usable
+        if self.index == self.entries.len() {
+            return Err(WriteError::TooManyEntries(self.entries.len()));
+        }
+
+        let result = self.write_next_entry(data);
+        self.poisoned = result.is_err();
+        result
+    }
+
+    fn write_next_entry(
+        &mut self,
+        mut data: impl Read,
+    ) -> Result<(), WriteError> {
+        let index = self.index;
+        let (_, size, key) = self.entries[index];
+
+        // XP/VX data key is the running key after the entry metadata
+        let mut key = if self.engine.is_older() {
+            self.write_entry_header_older()?;
+            self.key
+        } else {
+            key
+        };
+        let mut key_byte_pos = 0;
+        let mut remaining = size as usize;
+
+        while remaining != 0 {
+            let chunk_size = remaining.min(self.buf.len());
+            let read = data.read(&mut self.buf[..chunk_size])?;
+
+            if read == 0 {
+                return Err(WriteError::DataTooShort {
+                    index,
+                    expected: size,
+                    actual: size - remaining as u32,
+                });
+            }
+
+            Decrypter::xor_data_from(
+                &mut key,
+                &mut key_byte_pos,
+                &mut self.buf[..read],
+            );
+            self.writer.write_all(&self.buf[..read])?;
+
+            remaining -= read;
+        }
+
+        if data.read(&mut self.buf[..1])? != 0 {
+            return Err(WriteError::DataTooLong {
+                index,
+                expected: size,
+            });
+        }
+
+        self.index += 1;
+        Ok(())
+    }
+
+    /// Flushes the destination and returns it.
+    ///
+    /// # Errors
+    ///
+    /// - [`WriteError::Poisoned`] if a previous [`ArchiveWriter::write_entry`] call failed.
+    /// - [`WriteError::MissingEntries`] if data of some declared entries wasn't written.
+    /// - [`WriteError::Io`] if flushing the destination fails.
+    pub fn finish(mut self) -> Result<W, WriteError> {
+        if self.poisoned {
+            return Err(WriteError::Poisoned);
+        }
+
+        if self.index != self.entries.len() {
+            return Err(WriteError::MissingEntries {
+                written: self.index,
+                expected: self.entries.len(),
+            });
+        }
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
@@ -0,0 +1,133 @@
+#![allow(dead_code)]
+
+use proptest::{collection::vec, prelude::*};
+use rpgmad_lib::{ArchiveEntry, Decrypter, Engine};
+
+/// Decrypted file of a fixture archive: path and data.
+pub type FixtureFile = (Vec<u8>, Vec<u8>);
+
+/// Directories RPG Maker games store their files in.
+const DIRECTORIES: [&[u8]; 6] = [
+    b"Data",
+    b"Graphics\\Pictures",
+    b"Graphics\\Characters",
+    b"Audio\\BGM",
+    b"Audio\\SE",
+    b"Fonts",
+];
+
+/// Shift JIS encoded names: "マップ", "タイトル", "主人公", "効果音".
+const SHIFT_JIS_NAMES: [&[u8]; 4] = [
+    b"\x83}\x83b\x83v",
+    b"\x83^\x83C\x83g\x83\x8b",
+    b"\x8e\xe5\x90l\x8c\xf6",
+    b"\x8c\xf8\x89\xca\x89\xb9",
+];
+
+const EXTENSIONS: [&[u8]; 6] = [
+    b".rvdata2",
+    b".rvdata",
+    b".rxdata",
+    b".png",
+    b".ogg",
+    b".ttf",
+];
+
+pub fn engine() -> impl Strategy<Value = Engine> {
+    prop_oneof![Just(Engine::XP), Just(Engine::VX), Just(Engine::VXAce)]
+}
+
+/// Generates realistic paths with ASCII and Shift JIS names, and arbitrary byte strings, including empty ones.
+pub fn path() -> impl Strategy<Value = Vec<u8>> {
+    let name = prop_oneof![
+        "[A-Za-z0-9_]{1,16}".prop_map(String::into_bytes),
+        prop::sample::select(SHIFT_JIS_NAMES.to_vec()).prop_map(<[u8]>::to_vec),
+    ];
+
+    let realistic = (
+        prop::sample::select(DIRECTORIES.to_vec()),
+        name,
+        prop::sample::select(EXTENSIONS.to_vec()),
+    )
+        .prop_map(|(dir, name, ext)| {
+            let mut path = dir.to_vec();
+            path.push(b'\\');
+            path.extend_from_slice(&name);
+            path.extend_from_slice(ext);
+            path
+        });
+
+    prop_oneof![
+        8 => realistic,
+        1 => vec(any::<u8>(), 0..64),
+        1 => Just(Vec::new()),
+    ]
+}
+
+/// Generates file data, including empty files.
+pub fn data() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        1 => Just(Vec::new()),
+        8 => vec(any::<u8>(), 1..512),
+        1 => vec(any::<u8>(), 512..8192),
+    ]
+}
+
+pub fn files() -> impl Strategy<Value = Vec<FixtureFile>> {
+    vec((path(), data()), 0..16)
+}
+
+/// Returns files with a large file, that exceeds the sizes generated by [`files`].
+pub fn large_files() -> Vec<FixtureFile> {
+    let large = (0..=u8::MAX).cycle().take(3 << 20).collect::<Vec<_>>();
+
+    vec![
+        (b"Data\\Scripts.rvdata2".to_vec(), b"\x04\x08[\x00".to_vec()),
+        (b"Audio\\BGM\\Theme.ogg".to_vec(), large),
+        (b"Graphics\\Pictures\\Empty.png".to_vec(), Vec::new()),
+    ]
+}
+
+pub fn entries(files: &[FixtureFile]) -> Vec<ArchiveEntry<'_>> {
+    files
+        .iter()
+        .map(|(path, data)| ArchiveEntry { path, data })
+        .collect()
+}
+
+/// Builds encrypted fixture archive from `files`.
+pub fn build_archive(files: &[FixtureFile], engine: Engine) -> Vec<u8> {
+    let entries = entries(files);
+    let mut archive =
+        vec![0; Decrypter::encrypted_buffer_size(&entries, engine)];
+    Decrypter::new().encrypt(&entries, engine, &mut archive);
+    archive
+}
+
+/// Decrypts fixture archive back into files.
+pub fn decrypt_archive(mut archive: Vec<u8>) -> Vec<FixtureFile> {
+    let decrypter = Decrypter::new();
+
+    decrypter
+        .decrypt(&mut archive)
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.path.to_vec(), entry.data.to_vec())
+        })
+        .collect()
+}
+
+/// Reference byte-by-byte implementation of the entry data keystream: every four bytes, the key advances with `key * 7 + 3`.
+pub fn xor_scalar(mut key: u32, data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .enumerate()
+        .map(|(idx, byte)| {
+            if idx != 0 && idx % 4 == 0 {
+                key = key.wrapping_mul(7).wrapping_add(3);
+            }
+
+            byte ^ key.to_le_bytes()[idx % 4]
+        })
+        .collect()
+}
@@ -0,0 +1,367 @@
+mod common;
+
+use common::{
+    build_archive, data, decrypt_archive, engine, entries, files, large_files,
+    xor_scalar,
+};
+use proptest::{collection::vec, prelude::*};
+#[cfg(feature = "alloc")]
+use rpgmad_lib::{Archive, ArchiveIndex, ArchiveLayout, Patch};
+#[cfg(feature = "std")]
+use rpgmad_lib::{ArchiveEntry, ArchiveReader, EncryptOptions, EntryKeys};
+use rpgmad_lib::{
+    ArchiveView, AssetDecrypter, AssetKey, Decrypter, Engine, KeyStream, probe,
+};
+#[cfg(feature = "std")]
+use std::io::{Cursor, Read};
+
+proptest! {
+    #[test]
+    fn decrypt_encrypted_asset(data in data(), key in any::<[u8; 16]>()) {
+        let decrypter = AssetDecrypter::new(AssetKey::new(key));
+
+        let mut encrypted = vec![0; AssetDecrypter::encrypted_buffer_size(&data)];
+        decrypter.encrypt(&data, &mut encrypted).unwrap();
+
+        prop_assert_eq!(decrypter.decrypt(&mut encrypted).unwrap(), &data[..]);
+    }
+
+    #[test]
+    fn decrypt_encrypted(files in files(), engine in engine()) {
+        let archive = build_archive(&files, engine);
+        prop_assert_eq!(decrypt_archive(archive), files);
+    }
+
+    #[test]
+    fn encrypted_buffer_size_is_exact(files in files(), engine in engine()) {
+        let entries = entries(&files);
+        let size = Decrypter::encrypted_buffer_size(&entries, engine);
+
+        // Bytes, that are not written, keep the fill value and differ between the buffers.
+        let mut zeroed = vec![0x00; size + 16];
+        let mut filled = vec![0xFF; size + 16];
+        Decrypter::new().encrypt(&entries, engine, &mut zeroed);
+        Decrypter::new().encrypt(&entries, engine, &mut filled);
+
+        prop_assert_eq!(&zeroed[..size], &filled[..size]);
+        prop_assert!(zeroed[size..].iter().all(|&byte| byte == 0x00));
+        prop_assert!(filled[size..].iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn decrypt_apis_agree(files in files(), engine in engine()) {
+        let archive = build_archive(&files, engine);
+
+        // XP and VX archives are told apart by the first XP or VX data file.
+        let expected_engine = if engine.is_older() {
+            files
+                .iter()
+                .find_map(|(path, _)| Engine::from_data_path(path).filter(Engine::is_older))
+                .unwrap_or(Engine::XP)
+        } else {
+            engine
+        };
+
+        prop_assert_eq!(probe(&archive).unwrap().engine, expected_engine);
+
+        #[cfg(feature = "alloc")]
+        {
+            let index = ArchiveIndex::new(&archive).unwrap();
+            prop_assert_eq!(index.engine(), expected_engine);
+            prop_assert_eq!(index.entries().len(), files.len());
+
+            for (entry, (path, data)) in index.entries().iter().zip(&files) {
+                prop_assert_eq!(&entry.path, path);
+                prop_assert_eq!(&index.decrypt_entry(entry).unwrap(), data);
+            }
+
+            #[cfg(feature = "std")]
+            {
+                let mut reader = ArchiveReader::new(Cursor::new(&archive)).unwrap();
+                let reader_entries = reader.entries().collect::<Result<Vec<_>, _>>().unwrap();
+                prop_assert_eq!(index.entries(), reader_entries.as_slice());
+                prop_assert_eq!(reader.engine(), expected_engine);
+
+                for (entry, (_, data)) in index.entries().iter().zip(&files) {
+                    let mut read = Vec::new();
+                    reader.read_entry(entry, &mut read).unwrap();
+                    prop_assert_eq!(&read, data);
+                }
+            }
+        }
+
+        let view = ArchiveView::new(&archive).unwrap();
+        prop_assert_eq!(view.engine(), expected_engine);
+        prop_assert_eq!(view.entries().len(), files.len());
+
+        let mut path_buffer = [0; 64];
+        let mut data_buffer = [0; 8192];
+
+        for (entry, (path, data)) in view.entries().zip(&files) {
+            prop_assert_eq!(&*view.decrypt_path_into(&entry, &mut path_buffer).unwrap(), path.as_slice());
+            prop_assert_eq!(&*view.decrypt_entry_into(&entry, &mut data_buffer).unwrap(), data.as_slice());
+            prop_assert_eq!(view.get(path).unwrap().path_size, path.len());
+        }
+
+        #[cfg(feature = "alloc")]
+        {
+            let opened = Archive::open(&archive).unwrap();
+            prop_assert_eq!(opened, Archive::from_entries(entries(&files)));
+        }
+    }
+
+    #[test]
+    fn decrypted_entries_from_both_ends(
+        files in files(),
+        engine in engine(),
+        skip in 0..4usize,
+        split in any::<prop::sample::Index>(),
+    ) {
+        let mut archive = build_archive(&files, engine);
+        let decrypter = Decrypter::new();
+
+        let entries = decrypter.decrypt(&mut archive).unwrap();
+        prop_assert_eq!(entries.len(), files.len());
+
+        let reversed = entries
+            .rev()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.path.to_vec(), entry.data.to_vec())
+            })
+            .collect::<Vec<_>>();
+        prop_assert!(reversed.iter().eq(files.iter().rev()));
+
+        // Yielded entries are decrypted in place, so the archive is rebuilt for every pass.
+        let mut archive = build_archive(&files, engine);
+        let mut entries = decrypter.decrypt(&mut archive).unwrap();
+        let split = split.index(files.len() + 1);
+
+        for (path, data) in &files[..split] {
+            let entry = entries.next().unwrap().unwrap();
+            prop_assert_eq!((entry.path, entry.data), (path.as_slice(), data.as_slice()));
+        }
+
+        for (yielded, (path, data)) in files[split..].iter().rev().enumerate() {
+            prop_assert_eq!(entries.len(), files.len() - split - yielded);
+            let entry = entries.next_back().unwrap().unwrap();
+            prop_assert_eq!((entry.path, entry.data), (path.as_slice(), data.as_slice()));
+        }
+
+        prop_assert_eq!(entries.len(), 0);
+        prop_assert!(entries.next().is_none());
+
+        let mut archive = build_archive(&files, engine);
+        let skipped = decrypter.decrypt(&mut archive).unwrap().skip(skip);
+        prop_assert_eq!(skipped.len(), files.len().saturating_sub(skip));
+
+        for (entry, (path, data)) in skipped.zip(files.iter().skip(skip)) {
+            let entry = entry.unwrap();
+            prop_assert_eq!((entry.path, entry.data), (path.as_slice(), data.as_slice()));
+        }
+
+        let mut archive = build_archive(&files, engine);
+        let mut entries = decrypter.decrypt(&mut archive).unwrap();
+        let entry = entries
+            .nth_back(skip)
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.path.to_vec(), entry.data.to_vec())
+            });
+        prop_assert_eq!(entry.as_ref(), files.iter().rev().nth(skip));
+        prop_assert_eq!(entries.len(), files.len().saturating_sub(skip + 1));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn layout_round_trip(files in files(), engine in engine()) {
+        let archive = build_archive(&files, engine);
+        let opened = Archive::open(&archive).unwrap();
+
+        // Files with duplicate paths are merged when opening.
+        prop_assume!(opened.len() == files.len());
+
+        let layout = ArchiveLayout::new(&archive).unwrap();
+        prop_assert_eq!(opened.to_bytes_with_layout(&layout), archive);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn patch_round_trip(source in files(), target in files()) {
+        let mut source = Archive::from_entries(entries(&source));
+        let target = Archive::from_entries(entries(&target));
+
+        let patch = Patch::diff(&source, &target);
+        let patch = Patch::from_bytes(&patch.to_bytes()).unwrap();
+        patch.apply(&mut source).unwrap();
+
+        prop_assert_eq!(source.len(), target.len());
+
+        for file in target.files() {
+            prop_assert_eq!(source.get(&file.path), Some(file));
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn patch_edited_data(
+        data in vec(any::<u8>(), 0..4096),
+        inserted in vec(any::<u8>(), 0..64),
+        start in any::<prop::sample::Index>(),
+        removed in 0..64usize,
+    ) {
+        let start = start.index(data.len() + 1);
+        let end = (start + removed).min(data.len());
+
+        let mut edited = data.clone();
+        edited.splice(start..end, inserted);
+
+        let path = b"Data\\Map001.rvdata2".as_slice();
+        let mut source = Archive::new();
+        source.insert(path, data.as_slice());
+        let mut target = Archive::new();
+        target.insert(path, edited.as_slice());
+
+        let patch = Patch::diff(&source, &target);
+        patch.apply(&mut source).unwrap();
+        prop_assert_eq!(source, target);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_apis_agree(files in files(), engine in engine()) {
+        let entries = entries(&files);
+        let archive = build_archive(&files, engine);
+
+        let mut encrypted = vec![0; archive.len()];
+        Decrypter::new().encrypt_par(&entries, engine, &mut encrypted);
+        prop_assert_eq!(&encrypted, &archive);
+
+        let decrypter = Decrypter::new();
+        let decrypted = decrypter.decrypt_par(&mut encrypted).unwrap();
+        prop_assert_eq!(decrypted.len(), files.len());
+
+        for (entry, (path, data)) in decrypted.iter().zip(&files) {
+            prop_assert_eq!(entry.path, path.as_slice());
+            prop_assert_eq!(entry.data, data.as_slice());
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn keystream_matches_scalar(
+        data in vec(any::<u8>(), 0..2048),
+        key in any::<u32>(),
+        chunk_sizes in vec(1..40usize, 1..16),
+    ) {
+        let archive_entries = [ArchiveEntry {
+            path: b"Audio\\BGM\\Theme.ogg",
+            data: &data,
+        }];
+
+        let mut archive = vec![0; Decrypter::encrypted_buffer_size(&archive_entries, Engine::VXAce)];
+        let mut options = EncryptOptions {
+            base_key: 0,
+            entry_keys: EntryKeys::Fixed(key),
+        };
+        Decrypter::new().encrypt_with_options(&archive_entries, Engine::VXAce, &mut options, &mut archive);
+
+        // Entry data is stored at the end of the archive.
+        let expected = xor_scalar(key, &data);
+        prop_assert_eq!(&archive[archive.len() - data.len()..], expected.as_slice());
+
+        // Reading in chunks of arbitrary sizes continues the keystream from the middle of key words.
+        let mut reader = ArchiveReader::new(Cursor::new(&archive)).unwrap();
+        let entry = reader.next_entry().unwrap().unwrap();
+        let mut entry_reader = reader.entry_reader(&entry).unwrap();
+        let mut decrypted = Vec::new();
+
+        for &chunk_size in chunk_sizes.iter().cycle() {
+            let mut chunk = vec![0; chunk_size];
+            let read = entry_reader.read(&mut chunk).unwrap();
+
+            if read == 0 {
+                break;
+            }
+
+            decrypted.extend_from_slice(&chunk[..read]);
+        }
+
+        prop_assert_eq!(decrypted, data);
+    }
+
+    #[test]
+    fn keystream_seeks_to_any_offset(
+        data in vec(any::<u8>(), 0..2048),
+        key in any::<u32>(),
+        start in any::<prop::sample::Index>(),
+        len in 0..64usize,
+    ) {
+        let expected = xor_scalar(key, &data);
+        let start = start.index(data.len() + 1);
+        let end = (start + len).min(data.len());
+
+        let mut keystream = KeyStream::new(key);
+        keystream.seek(start as u64);
+
+        let mut range = data[start..end].to_vec();
+        keystream.apply_keystream(&mut range);
+        prop_assert_eq!(range.as_slice(), &expected[start..end]);
+        prop_assert_eq!(keystream.position(), end as u64);
+
+        let archive = build_archive(&[(b"Audio\\BGM\\Theme.ogg".to_vec(), data.clone())], Engine::VXAce);
+
+        let view = ArchiveView::new(&archive).unwrap();
+        let entry = view.entries().next().unwrap();
+        let mut out = vec![0; len];
+        let decrypted = view.decrypt_entry_range_into(&entry, start as u64, &mut out).unwrap();
+        prop_assert_eq!(&*decrypted, &data[start..end]);
+
+        #[cfg(feature = "std")]
+        {
+            let mut reader = ArchiveReader::new(Cursor::new(&archive)).unwrap();
+            let entry = reader.next_entry().unwrap().unwrap();
+            let decrypted = reader.read_entry_range(&entry, start as u64, len).unwrap();
+            prop_assert_eq!(decrypted.as_slice(), &data[start..end]);
+        }
+    }
+
+    #[test]
+    fn truncated_archive_does_not_panic(
+        files in files(),
+        engine in engine(),
+        cut in any::<prop::sample::Index>(),
+    ) {
+        let mut archive = build_archive(&files, engine);
+        archive.truncate(cut.index(archive.len()));
+
+        #[cfg(feature = "alloc")]
+        let _ = ArchiveIndex::new(&archive);
+
+        let decrypter = Decrypter::new();
+        let decrypted = decrypter.decrypt(&mut archive);
+
+        if let Ok(decrypted) = decrypted {
+            decrypted.for_each(drop);
+        }
+
+        #[cfg(feature = "rayon")]
+        {
+            let mut archive = build_archive(&files, engine);
+            archive.truncate(cut.index(archive.len()));
+            let _ = Decrypter::new().decrypt_par(&mut archive);
+        }
+    }
+}
+
+#[test]
+// Megabytes of keystream take too long under Miri, and are covered by the generated files
+#[cfg_attr(miri, ignore)]
+fn decrypt_encrypted_large() {
+    let files = large_files();
+
+    for engine in [Engine::XP, Engine::VXAce] {
+        let archive = build_archive(&files, engine);
+        assert_eq!(decrypt_archive(archive), files);
+    }
+}
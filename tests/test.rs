@@ -1,5 +1,29 @@
 use marshal_rs::load;
-use rpgmad_lib::{ArchiveEntry, Decrypter, Engine};
+#[cfg(any(feature = "std", feature = "encoding"))]
+use rpgmad_lib::PathEncoding;
+use rpgmad_lib::{
+    ASSET_HEADER, ASSET_HEADER_SIZE, ArchiveEntry, ArchiveInfo, ArchiveView,
+    AssetDecrypter, AssetError, AssetKey, Decrypter, Engine, ExtractError,
+    asset_extension, probe,
+};
+#[cfg(feature = "alloc")]
+use rpgmad_lib::{
+    Archive, ArchiveIndex, ArchiveLayout, DecryptOptions, EncryptOptions,
+    EntryKeys, Patch, PatchError, PatchOperation,
+};
+#[cfg(feature = "std")]
+use rpgmad_lib::{
+    ArchiveReader, ArchiveWriter, EntryHeader, ExtractFileError,
+    ExtractOptions, PackError, PackOptions, ReadError, WriteError, pack_dir,
+    probe_file, sanitize_entry_path,
+};
+#[cfg(feature = "std")]
+use std::io::{Cursor, Read, Seek, SeekFrom};
+#[cfg(feature = "alloc")]
+use std::{
+    env::temp_dir,
+    fs::{create_dir_all, remove_dir_all, write},
+};
 use std::{env::var, fs::read, path::PathBuf};
 
 fn is_valid_png(buf: &[u8]) -> bool {
@@ -10,26 +34,24 @@ fn is_decrypted_valid<'a>(
     decrypted_entries: &[ArchiveEntry<'a>],
 ) -> Result<(), String> {
     for entry in decrypted_entries {
-        let path = std::str::from_utf8(&entry.path).unwrap();
+        let path = std::str::from_utf8(entry.path).unwrap();
         let ext = path.rsplit_once('.').unwrap().1;
 
         if ["rvdata", "rxdata", "rvdata2"].contains(&ext) {
-            if load(&entry.data, None).is_err() {
+            if load(entry.data, None).is_err() {
                 return Err(format!(
                     "Decrypting RPG Maker data file {} failed.",
                     PathBuf::from(
-                        String::from_utf8_lossy(&entry.path).into_owned()
+                        String::from_utf8_lossy(entry.path).into_owned()
                     )
                     .display()
                 ));
             }
-        } else if ext == "png" && !is_valid_png(&entry.data) {
+        } else if ext == "png" && !is_valid_png(entry.data) {
             return Err(format!(
                 "Decrypting RPG Maker image {} failed.",
-                PathBuf::from(
-                    String::from_utf8_lossy(&entry.path).into_owned()
-                )
-                .display()
+                PathBuf::from(String::from_utf8_lossy(entry.path).into_owned())
+                    .display()
             ));
         };
     }
@@ -39,68 +61,1948 @@ fn is_decrypted_valid<'a>(
 
 #[test]
 fn decrypt_vxace() {
-    let archive_path =
-        PathBuf::from(var("RPGMARD_VXACE_ARCHIVE_PATH").unwrap());
+    // Tests, that require a real archive, are skipped without it.
+    let Ok(archive_path) = var("RPGMARD_VXACE_ARCHIVE_PATH") else {
+        return;
+    };
     let mut archive_content = read(&archive_path).unwrap();
-    let mut decrypter = Decrypter::new();
+    let decrypter = Decrypter::new();
     let decrypted_files = decrypter.decrypt(&mut archive_content).unwrap();
-    let decrypted_files = decrypted_files.collect::<Vec<_>>();
+    let decrypted_files =
+        decrypted_files.collect::<Result<Vec<_>, _>>().unwrap();
     is_decrypted_valid(&decrypted_files).unwrap();
 }
 
 #[test]
 fn decrypt_older() {
-    let archive_path =
-        PathBuf::from(var("RPGMARD_OLDER_ARCHIVE_PATH").unwrap());
+    let Ok(archive_path) = var("RPGMARD_OLDER_ARCHIVE_PATH") else {
+        return;
+    };
     let mut archive_content = read(&archive_path).unwrap();
-    let mut decrypter = Decrypter::new();
+    let decrypter = Decrypter::new();
     let decrypted_files = decrypter.decrypt(&mut archive_content).unwrap();
-    let decrypted_files = decrypted_files.collect::<Vec<_>>();
+    let decrypted_files =
+        decrypted_files.collect::<Result<Vec<_>, _>>().unwrap();
     is_decrypted_valid(&decrypted_files).unwrap();
 }
 
+#[cfg(feature = "alloc")]
 #[test]
 fn encrypt_vxace() {
-    let archive_path =
-        PathBuf::from(var("RPGMARD_VXACE_ARCHIVE_PATH").unwrap());
+    let Ok(archive_path) = var("RPGMARD_VXACE_ARCHIVE_PATH") else {
+        return;
+    };
     let mut archive_content = read(&archive_path).unwrap();
-    let mut decrypter = Decrypter::new();
+    let original_content = archive_content.clone();
+    let layout = ArchiveLayout::new(&original_content).unwrap();
+
+    let decrypter = Decrypter::new();
     let decrypted_files = decrypter.decrypt(&mut archive_content).unwrap();
-    let decrypted_files = decrypted_files.collect::<Vec<_>>();
+    let decrypted_files =
+        decrypted_files.collect::<Result<Vec<_>, _>>().unwrap();
     is_decrypted_valid(&decrypted_files).unwrap();
 
     let encrypted_data_size =
         Decrypter::encrypted_buffer_size(&decrypted_files, Engine::VXAce);
-    let mut encrypted = Vec::new();
-    encrypted.resize(encrypted_data_size, 0);
-    let _ = Decrypter::new().encrypt(
-        &decrypted_files,
-        Engine::VXAce,
-        &mut encrypted,
-    );
+    let mut encrypted = vec![0; encrypted_data_size];
+    Decrypter::new().encrypt(&decrypted_files, Engine::VXAce, &mut encrypted);
+
+    assert!(encrypted.len() == original_content.len());
 
-    assert!(encrypted.len() == archive_content.len());
+    let archive = Archive::from_entries(decrypted_files);
+    assert!(archive.to_bytes_with_layout(&layout) == original_content);
 }
 
 #[test]
 fn encrypt_older() {
-    let archive_path =
-        PathBuf::from(var("RPGMARD_OLDER_ARCHIVE_PATH").unwrap());
+    let Ok(archive_path) = var("RPGMARD_OLDER_ARCHIVE_PATH") else {
+        return;
+    };
     let mut archive_content = read(&archive_path).unwrap();
-    let mut decrypter = Decrypter::new();
+    let decrypter = Decrypter::new();
     let decrypted_files = decrypter.decrypt(&mut archive_content).unwrap();
-    let decrypted_files = decrypted_files.collect::<Vec<_>>();
+    let decrypted_files =
+        decrypted_files.collect::<Result<Vec<_>, _>>().unwrap();
     is_decrypted_valid(&decrypted_files).unwrap();
 
     let encrypted_data_size =
-        Decrypter::encrypted_buffer_size(&decrypted_files, Engine::Older);
-    let mut encrypted = Vec::new();
-    encrypted.resize(encrypted_data_size, 0);
-    let _ = Decrypter::new().encrypt(
-        &decrypted_files,
-        Engine::Older,
+        Decrypter::encrypted_buffer_size(&decrypted_files, Engine::XP);
+    let mut encrypted = vec![0; encrypted_data_size];
+    Decrypter::new().encrypt(&decrypted_files, Engine::XP, &mut encrypted);
+
+    assert!(archive_content == encrypted);
+}
+
+fn encrypt_vxace_fixture() -> Vec<u8> {
+    let archive_entries = [
+        ArchiveEntry {
+            path: b"Data\\Map001.rvdata2",
+            data: b"\x04\x08[\x00",
+        },
+        ArchiveEntry {
+            path: b"Graphics\\Pictures\\Title.png",
+            data: b"\x89PNG\r\n\x1a\n",
+        },
+    ];
+
+    let mut encrypted =
+        vec![
+            0;
+            Decrypter::encrypted_buffer_size(&archive_entries, Engine::VXAce)
+        ];
+    Decrypter::new().encrypt(&archive_entries, Engine::VXAce, &mut encrypted);
+
+    encrypted
+}
+
+#[test]
+fn decrypt_truncated_header() {
+    let mut archive_content = b"RGSS".to_vec();
+    let decrypter = Decrypter::new();
+
+    assert!(matches!(
+        decrypter.decrypt(&mut archive_content),
+        Err(ExtractError::UnexpectedEof {
+            offset: 0,
+            needed: 3
+        })
+    ));
+}
+
+#[test]
+fn decrypt_truncated_metadata() {
+    let mut archive_content = encrypt_vxace_fixture();
+    archive_content.truncate(20);
+
+    let decrypter = Decrypter::new();
+    let mut decrypted_files = decrypter.decrypt(&mut archive_content).unwrap();
+
+    assert!(matches!(
+        decrypted_files.next(),
+        Some(Err(ExtractError::UnexpectedEof { offset: 20, .. }))
+    ));
+    assert!(decrypted_files.next().is_none());
+}
+
+#[test]
+fn decrypt_truncated_data() {
+    let mut archive_content = encrypt_vxace_fixture();
+
+    archive_content.pop();
+
+    let decrypter = Decrypter::new();
+    let decrypted_files = decrypter
+        .decrypt(&mut archive_content)
+        .unwrap()
+        .collect::<Vec<_>>();
+
+    assert!(decrypted_files[0].is_ok());
+    assert!(matches!(
+        decrypted_files[1],
+        Err(ExtractError::EntryOutOfBounds {
+            index: 1,
+            size: 8,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn decrypt_corrupted_path_size() {
+    let mut archive_content = encrypt_vxace_fixture();
+
+    // Path size of the first entry, encrypted with base key `0 * 9 + 3`.
+    archive_content[24..28].copy_from_slice(&(u32::MAX ^ 3).to_le_bytes());
+
+    let decrypter = Decrypter::new();
+    let mut decrypted_files = decrypter.decrypt(&mut archive_content).unwrap();
+
+    assert!(matches!(
+        decrypted_files.next(),
+        Some(Err(ExtractError::PathTooLong { index: 0, .. }))
+    ));
+}
+
+#[test]
+fn decrypt_unordered_entries() {
+    let mut archive_content = encrypt_vxace_fixture();
+    let second_offset = 12 + 16 + b"Data\\Map001.rvdata2".len();
+
+    // Data of the second entry is stored before data of the first one.
+    let data_start = archive_content.len() - 12;
+    archive_content[data_start..].rotate_left(4);
+    archive_content[12..16]
+        .copy_from_slice(&((data_start as u32 + 8) ^ 3).to_le_bytes());
+    archive_content[second_offset..second_offset + 4]
+        .copy_from_slice(&(data_start as u32 ^ 3).to_le_bytes());
+    let decrypter = Decrypter::new();
+
+    #[cfg(feature = "alloc")]
+    {
+        let unordered_content = archive_content.clone();
+        let decrypted_files = decrypter
+            .decrypt(&mut archive_content)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(decrypted_files[0].path, b"Data\\Map001.rvdata2");
+        assert_eq!(decrypted_files[0].data, b"\x04\x08[\x00");
+        assert!(is_valid_png(decrypted_files[1].data));
+
+        let mut archive_content = unordered_content;
+        let mut decrypted_files =
+            decrypter.decrypt(&mut archive_content).unwrap();
+
+        assert!(is_valid_png(
+            decrypted_files.next_back().unwrap().unwrap().data
+        ));
+        assert_eq!(
+            decrypted_files.next_back().unwrap().unwrap().data,
+            b"\x04\x08[\x00"
+        );
+        assert!(decrypted_files.next_back().is_none());
+    }
+
+    // Without `alloc`, data can only be split off in the order of entries.
+    #[cfg(not(feature = "alloc"))]
+    assert!(matches!(
+        decrypter.decrypt(&mut archive_content).unwrap().nth(1),
+        Some(Err(ExtractError::OverlappingEntries { index: 1 }))
+    ));
+
+    let mut archive_content = encrypt_vxace_fixture();
+
+    // Offset of the second entry, pointed at the data of the first one.
+    archive_content.copy_within(12..16, second_offset);
+
+    let mut decrypted_files = decrypter.decrypt(&mut archive_content).unwrap();
+
+    assert!(decrypted_files.next().unwrap().is_ok());
+    assert!(matches!(
+        decrypted_files.next(),
+        Some(Err(ExtractError::OverlappingEntries { index: 1 }))
+    ));
+    assert!(decrypted_files.next().is_none());
+
+    let mut archive_content = encrypt_vxace_fixture();
+
+    // Offset of the first entry, pointed at its own metadata.
+    archive_content[12..16].copy_from_slice(&(20u32 ^ 3).to_le_bytes());
+
+    let decrypter = Decrypter::new();
+    let mut decrypted_files = decrypter.decrypt(&mut archive_content).unwrap();
+
+    assert!(matches!(
+        decrypted_files.next(),
+        Some(Err(ExtractError::OverlappingEntries { index: 0 }))
+    ));
+}
+
+#[test]
+fn decrypt_skips_entries_without_decrypting() {
+    let mut archive_content = encrypt_vxace_fixture();
+    let original_content = archive_content.clone();
+
+    let decrypter = Decrypter::new();
+    let mut decrypted_files = decrypter.decrypt(&mut archive_content).unwrap();
+
+    assert_eq!(decrypted_files.len(), 2);
+    assert_eq!(decrypted_files.size_hint(), (2, Some(2)));
+
+    let entry = decrypted_files.nth(1).unwrap().unwrap();
+    assert_eq!(entry.data, b"\x89PNG\r\n\x1a\n");
+    assert_eq!(decrypted_files.len(), 0);
+    assert!(decrypted_files.next().is_none());
+
+    // Path and data of the skipped entry are left encrypted.
+    let first_path = 12 + 16..12 + 16 + b"Data\\Map001.rvdata2".len();
+    assert_eq!(
+        archive_content[first_path.clone()],
+        original_content[first_path]
+    );
+
+    let mut archive_content = original_content.clone();
+    let mut decrypted_files = decrypter.decrypt(&mut archive_content).unwrap();
+
+    let entry = decrypted_files.next_back().unwrap().unwrap();
+    assert_eq!(entry.path, b"Graphics\\Pictures\\Title.png");
+
+    let entry = decrypted_files.next_back().unwrap().unwrap();
+    assert_eq!(entry.path, b"Data\\Map001.rvdata2");
+    assert!(decrypted_files.next_back().is_none());
+
+    // Corrupted entries are counted as a single error, which is yielded first from the back.
+    let mut archive_content = original_content;
+    let second_offset = 12 + 16 + b"Data\\Map001.rvdata2".len();
+    archive_content.copy_within(12..16, second_offset);
+
+    let mut decrypted_files = decrypter.decrypt(&mut archive_content).unwrap();
+    assert_eq!(decrypted_files.len(), 2);
+    assert!(matches!(
+        decrypted_files.next_back(),
+        Some(Err(ExtractError::OverlappingEntries { index: 1 }))
+    ));
+    assert_eq!(decrypted_files.len(), 1);
+    assert_eq!(
+        decrypted_files.next_back().unwrap().unwrap().path,
+        b"Data\\Map001.rvdata2"
+    );
+    assert!(decrypted_files.next().is_none());
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn decrypt_par_overlapping_entries() {
+    let mut archive_content = encrypt_vxace_fixture();
+    let original_content = archive_content.clone();
+
+    // Offset of the second entry, pointed at the data of the first one.
+    let second_offset = 12 + 16 + b"Data\\Map001.rvdata2".len();
+    archive_content.copy_within(12..16, second_offset);
+    let corrupted_content = archive_content.clone();
+
+    assert!(matches!(
+        Decrypter::new().decrypt_par(&mut archive_content),
+        Err(ExtractError::OverlappingEntries { index: 1 })
+    ));
+    assert_eq!(archive_content, corrupted_content);
+
+    // Offset of the first entry, pointed at the metadata fields of the second one.
+    archive_content = original_content.clone();
+    archive_content[12..16]
+        .copy_from_slice(&(second_offset as u32 ^ 3).to_le_bytes());
+    let corrupted_content = archive_content.clone();
+
+    assert!(matches!(
+        Decrypter::new().decrypt_par(&mut archive_content),
+        Err(ExtractError::OverlappingEntries { index: 0 })
+    ));
+    assert_eq!(archive_content, corrupted_content);
+
+    archive_content = original_content;
+    let decrypter = Decrypter::new();
+    let decrypted_files = decrypter.decrypt_par(&mut archive_content).unwrap();
+
+    assert_eq!(decrypted_files.len(), 2);
+    assert_eq!(decrypted_files[0].path, b"Data\\Map001.rvdata2");
+    assert_eq!(decrypted_files[1].data, b"\x89PNG\r\n\x1a\n");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn read_streaming_vxace() {
+    let mut archive_content = encrypt_vxace_fixture();
+
+    // Archive doesn't have to start at the beginning of the stream.
+    let mut stream = b"prefix".to_vec();
+    stream.extend_from_slice(&archive_content);
+    let mut cursor = Cursor::new(stream);
+    cursor.set_position(6);
+
+    let mut reader = ArchiveReader::new(cursor).unwrap();
+    assert_eq!(reader.engine(), Engine::VXAce);
+
+    let entries = reader.entries().collect::<Result<Vec<_>, _>>().unwrap();
+
+    let decrypter = Decrypter::new();
+    let decrypted_files = decrypter
+        .decrypt(&mut archive_content)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(entries.len(), decrypted_files.len());
+
+    for (entry, decrypted) in entries.iter().zip(&decrypted_files) {
+        assert_eq!(entry.path, decrypted.path);
+
+        let mut data = Vec::new();
+        reader.read_entry(entry, &mut data).unwrap();
+        assert_eq!(data, decrypted.data);
+
+        // Reading in small chunks must produce the same data.
+        let mut entry_reader = reader.entry_reader(entry).unwrap();
+        let mut chunked = Vec::new();
+        let mut chunk = [0; 3];
+
+        loop {
+            let read = entry_reader.read(&mut chunk).unwrap();
+
+            if read == 0 {
+                break;
+            }
+
+            chunked.extend_from_slice(&chunk[..read]);
+        }
+
+        assert_eq!(chunked, decrypted.data);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn read_entry_ranges() {
+    let data = (0..=u8::MAX).collect::<Vec<_>>();
+    let archive_entries = [ArchiveEntry {
+        path: b"Audio\\BGM\\Theme1.ogg",
+        data: &data,
+    }];
+    let archive_content = encrypt_with(&archive_entries, Engine::XP);
+
+    let mut reader = ArchiveReader::new(Cursor::new(&archive_content)).unwrap();
+    let entry = reader.next_entry().unwrap().unwrap();
+
+    assert_eq!(
+        reader.read_entry_range(&entry, 101, 7).unwrap(),
+        &data[101..108]
+    );
+    // Ranges are truncated at the end of the entry.
+    assert_eq!(
+        reader.read_entry_range(&entry, 250, 100).unwrap(),
+        &data[250..]
+    );
+    assert!(reader.read_entry_range(&entry, 1000, 4).unwrap().is_empty());
+
+    let mut entry_reader = reader.entry_reader(&entry).unwrap();
+    let mut chunk = [0; 4];
+
+    assert_eq!(entry_reader.seek(SeekFrom::End(-3)).unwrap(), 253);
+    assert_eq!(entry_reader.read(&mut chunk).unwrap(), 3);
+    assert_eq!(chunk[..3], data[253..]);
+    assert_eq!(entry_reader.remaining(), 0);
+
+    assert_eq!(entry_reader.seek(SeekFrom::Current(-130)).unwrap(), 126);
+    entry_reader.read_exact(&mut chunk).unwrap();
+    assert_eq!(chunk, data[126..130]);
+    assert_eq!(entry_reader.remaining(), 126);
+
+    assert!(entry_reader.seek(SeekFrom::Current(-131)).is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn read_streaming_truncated() {
+    let mut archive_content = encrypt_vxace_fixture();
+    archive_content.truncate(20);
+
+    let mut reader = ArchiveReader::new(Cursor::new(archive_content)).unwrap();
+
+    assert!(matches!(
+        reader.next_entry(),
+        Err(ReadError::Extract(ExtractError::UnexpectedEof {
+            offset: 20,
+            ..
+        }))
+    ));
+    assert!(reader.next_entry().unwrap().is_none());
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn index_vxace() {
+    let archive_content = encrypt_vxace_fixture();
+    let index = ArchiveIndex::new(&archive_content).unwrap();
+
+    assert_eq!(index.engine(), Engine::VXAce);
+    assert_eq!(index.entries().len(), 2);
+    assert_eq!(index.entries()[0].path, b"Data\\Map001.rvdata2");
+    assert_eq!(index.entries()[1].size, 8);
+
+    let entry = index.get(b"Graphics\\Pictures\\Title.png").unwrap();
+    assert!(is_valid_png(&index.decrypt_entry(entry).unwrap()));
+    assert!(index.get(b"Graphics/Pictures/Title.png").is_none());
+
+    // Index must leave the archive untouched.
+    assert_eq!(archive_content, encrypt_vxace_fixture());
+}
+
+#[test]
+fn view_archives() {
+    let archive_content = encrypt_vxace_fixture();
+    let view = ArchiveView::new(&archive_content).unwrap();
+
+    assert_eq!(view.engine(), Engine::VXAce);
+    assert_eq!(view.len(), 2);
+
+    let entry = view.get(b"Graphics\\Pictures\\Title.png").unwrap();
+    assert_eq!(entry.index, 1);
+    assert!(view.get(b"Graphics/Pictures/Title.png").is_none());
+
+    let mut data = [0; 16];
+    assert!(is_valid_png(
+        view.decrypt_entry_into(&entry, &mut data).unwrap()
+    ));
+    assert!(matches!(
+        view.decrypt_entry_into(&entry, &mut data[..4]),
+        Err(ExtractError::BufferTooSmall {
+            needed: 8,
+            available: 4
+        })
+    ));
+
+    // View must leave the archive untouched.
+    assert_eq!(archive_content, encrypt_vxace_fixture());
+
+    // Paths, longer than the stack buffer of `ArchiveView::get`, are compared in chunks.
+    let long_path = [b'a'; 100];
+    let archive_entries = [
+        ArchiveEntry {
+            path: b"Data\\Map001.rvdata",
+            data: b"\x04\x080",
+        },
+        ArchiveEntry {
+            path: &long_path,
+            data: b"long",
+        },
+    ];
+    let archive_content = encrypt_with(&archive_entries, Engine::VX);
+    let view = ArchiveView::new(&archive_content).unwrap();
+
+    assert_eq!(view.engine(), Engine::VX);
+
+    let entry = view.get(&long_path).unwrap();
+    let mut path = [0; 100];
+    assert_eq!(
+        view.decrypt_path_into(&entry, &mut path).unwrap(),
+        long_path
+    );
+    assert!(view.get(&long_path[..99]).is_none());
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mapped_archives() {
+    let archive_dir =
+        temp_dir().join(format!("rpgmad-mmap-{}", std::process::id()));
+    create_dir_all(&archive_dir).unwrap();
+
+    let archive_path = archive_dir.join("Game.rgss3a");
+    let archive_content = encrypt_vxace_fixture();
+    write(&archive_path, &archive_content).unwrap();
+
+    // SAFETY: Archive file is only written to after the maps are dropped.
+    let archive =
+        unsafe { rpgmad_lib::MappedArchive::open(&archive_path) }.unwrap();
+    let view = archive.view();
+
+    assert_eq!(archive.as_bytes(), archive_content);
+    assert_eq!(view.engine(), Engine::VXAce);
+    assert_eq!(view.entries().len(), 2);
+
+    let entry = view.get(b"Graphics\\Pictures\\Title.png").unwrap();
+    let mut data = [0; 8];
+    assert!(is_valid_png(
+        view.decrypt_entry_into(&entry, &mut data).unwrap()
+    ));
+
+    drop(archive);
+
+    // Copy-on-write map is decrypted in-place, leaving the file untouched.
+    // SAFETY: Archive file is only written to after the maps are dropped.
+    let mut archive =
+        unsafe { rpgmad_lib::MappedArchiveMut::open(&archive_path) }.unwrap();
+    let decrypter = Decrypter::new();
+    let decrypted_files = decrypter
+        .decrypt(&mut archive)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(decrypted_files[0].path, b"Data\\Map001.rvdata2");
+    assert_eq!(read(&archive_path).unwrap(), archive_content);
+
+    drop(archive);
+    write(&archive_path, b"RGSSAD\0\x02").unwrap();
+    assert!(matches!(
+        // SAFETY: Archive file isn't modified while it's mapped.
+        unsafe { rpgmad_lib::MappedArchive::open(&archive_path) },
+        Err(ReadError::Extract(ExtractError::InvalidEngine(2)))
+    ));
+
+    remove_dir_all(&archive_dir).unwrap();
+}
+
+#[test]
+fn probe_archives() {
+    let info = probe(&encrypt_vxace_fixture()).unwrap();
+    assert_eq!(
+        info,
+        ArchiveInfo {
+            engine: Engine::VXAce,
+            entry_count: 2,
+            data_size: 12,
+            base_key: 0,
+        }
+    );
+
+    for (engine, data_path) in [
+        (Engine::XP, &b"Data\\Map001.rxdata"[..]),
+        (Engine::VX, &b"Data\\Map001.RVDATA"[..]),
+    ] {
+        let archive_entries = [
+            ArchiveEntry {
+                path: b"Graphics\\Pictures\\Title.png",
+                data: b"\x89PNG\r\n\x1a\n",
+            },
+            ArchiveEntry {
+                path: data_path,
+                data: b"\x04\x08[\x00",
+            },
+        ];
+
+        let info = probe(&encrypt_with(&archive_entries, engine)).unwrap();
+        assert_eq!(
+            info,
+            ArchiveInfo {
+                engine,
+                entry_count: 2,
+                data_size: 12,
+                base_key: 0xDEAD_CAFE,
+            }
+        );
+    }
+
+    let mut truncated = encrypt_vxace_fixture();
+    truncated.truncate(20);
+    assert!(matches!(
+        probe(&truncated),
+        Err(ExtractError::UnexpectedEof { .. })
+    ));
+
+    #[cfg(feature = "std")]
+    {
+        // File is probed from its metadata, and agrees with the probed data.
+        let archive_dir = temp_dir()
+            .join(format!("rpgmad-probe-file-{}", std::process::id()));
+        create_dir_all(&archive_dir).unwrap();
+
+        let archive_path = archive_dir.join("Game.rgss3a");
+        write(&archive_path, encrypt_vxace_fixture()).unwrap();
+        assert_eq!(
+            probe_file(&archive_path).unwrap(),
+            probe(&encrypt_vxace_fixture()).unwrap()
+        );
+
+        write(&archive_path, &truncated).unwrap();
+        assert!(matches!(
+            probe_file(&archive_path),
+            Err(ReadError::Extract(ExtractError::UnexpectedEof { .. }))
+        ));
+
+        assert!(matches!(
+            probe_file(archive_dir.join("Missing.rgss3a")),
+            Err(ReadError::Io(_))
+        ));
+
+        remove_dir_all(&archive_dir).unwrap();
+    }
+
+    for engine in [Engine::XP, Engine::VX, Engine::VXAce] {
+        assert_eq!(Engine::from_extension(engine.extension()), Some(engine));
+    }
+
+    assert_eq!(Engine::from_extension(".RGSSAD"), Some(Engine::XP));
+    assert_eq!(Engine::from_extension("rvdata2"), None);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn detect_older_engine() {
+    let mut archive = Archive::new();
+    archive.insert(&b"Graphics\\Pictures\\Title.png"[..], &b"\x89PNG"[..]);
+    assert_eq!(archive.detect_engine(), None);
+
+    // Archive without data files is reported as XP, unless its file name tells otherwise.
+    let base_dir =
+        temp_dir().join(format!("rpgmad-probe-{}", std::process::id()));
+    create_dir_all(&base_dir).unwrap();
+
+    let archive_path = base_dir.join("Game.rgss2a");
+    write(&archive_path, archive.to_bytes(Engine::VX)).unwrap();
+    assert_eq!(
+        probe(&read(&archive_path).unwrap()).unwrap().engine,
+        Engine::XP
+    );
+    #[cfg(feature = "std")]
+    assert_eq!(probe_file(&archive_path).unwrap().engine, Engine::VX);
+
+    archive.insert(&b"Data\\Map001.rxdata"[..], &b"\x04\x080"[..]);
+    assert_eq!(archive.detect_engine(), Some(Engine::XP));
+
+    // Data files take precedence over the file name.
+    write(&archive_path, archive.to_bytes(Engine::XP)).unwrap();
+    #[cfg(feature = "std")]
+    assert_eq!(probe_file(&archive_path).unwrap().engine, Engine::XP);
+
+    let archive_content = read(&archive_path).unwrap();
+    assert_eq!(
+        ArchiveIndex::new(&archive_content).unwrap().engine(),
+        Engine::XP
+    );
+
+    #[cfg(feature = "std")]
+    {
+        let mut reader =
+            ArchiveReader::new(Cursor::new(&archive_content)).unwrap();
+        assert_eq!(reader.engine(), Engine::XP);
+        reader.entries().for_each(drop);
+        assert_eq!(reader.engine(), Engine::XP);
+    }
+
+    // VX Ace archives are never reported as XP or VX.
+    let vxace_path = base_dir.join("Game.rgssad");
+    write(&vxace_path, archive.to_bytes(Engine::VXAce)).unwrap();
+    #[cfg(feature = "std")]
+    assert_eq!(probe_file(&vxace_path).unwrap().engine, Engine::VXAce);
+
+    assert_eq!(Engine::from_file_name("GAME.RGSS3A"), Some(Engine::VXAce));
+    assert_eq!(Engine::from_file_name("rgssad"), None);
+    assert_eq!(Engine::VX.data_extension(), "rvdata");
+    assert_eq!(
+        Engine::from_data_path(b"Data\\Scripts.rvdata2"),
+        Some(Engine::VXAce)
+    );
+
+    remove_dir_all(base_dir).unwrap();
+}
+
+/// Re-encrypts VX Ace archive metadata with `metadata_key`, keeping the stored base key, like modified RGSS libraries do.
+#[cfg(feature = "alloc")]
+fn rekey_vxace(archive_content: &mut [u8], metadata_key: u32) {
+    // Stock metadata key, derived from the zero base key
+    let old_key_bytes = 3u32.to_le_bytes();
+    let new_key_bytes = metadata_key.to_le_bytes();
+    let mut pos = 12;
+
+    loop {
+        let read_u32 = |pos: usize| {
+            u32::from_le_bytes(
+                archive_content[pos..pos + 4].try_into().unwrap(),
+            ) ^ 3
+        };
+
+        let offset = read_u32(pos);
+        let path_size = read_u32(pos + 12) as usize;
+
+        let record_size = if offset == 0 { 16 } else { 16 + path_size };
+
+        for idx in 0..record_size {
+            // Path starts 16 bytes after the record start, so key bytes stay aligned
+            archive_content[pos + idx] ^=
+                old_key_bytes[idx % 4] ^ new_key_bytes[idx % 4];
+        }
+
+        pos += record_size;
+
+        if offset == 0 {
+            break;
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn decrypt_with_custom_key() {
+    let metadata_key = 0x1234_5678;
+    let mut archive_content = encrypt_vxace_fixture();
+    let mut expected = archive_content.clone();
+    rekey_vxace(&mut archive_content, metadata_key);
+
+    // Stock key derivation doesn't decrypt the archive.
+    assert!(ArchiveIndex::new(&archive_content).is_err());
+
+    let options = DecryptOptions::recover(&archive_content).unwrap();
+    assert_eq!(options.base_key, Some(metadata_key));
+
+    let index = ArchiveIndex::with_options(&archive_content, &options).unwrap();
+
+    #[cfg(feature = "std")]
+    {
+        let mut reader = ArchiveReader::with_options(
+            Cursor::new(&archive_content),
+            &options,
+        )
+        .unwrap();
+        let reader_entries =
+            reader.entries().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(index.entries(), reader_entries.as_slice());
+    }
+
+    let mut decrypted_content = archive_content.clone();
+    let decrypter = Decrypter::with_options(options);
+    let decrypted_files = decrypter
+        .decrypt(&mut decrypted_content)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    let expected_files = Decrypter::new()
+        .decrypt(&mut expected)
+        .unwrap()
+        .map(|entry| {
+            entry.map(|entry| (entry.path.to_vec(), entry.data.to_vec()))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(decrypted_files.len(), expected_files.len());
+
+    for ((decrypted, entry), (path, data)) in decrypted_files
+        .iter()
+        .zip(index.entries())
+        .zip(&expected_files)
+    {
+        assert_eq!(decrypted.path, path.as_slice());
+        assert_eq!(decrypted.data, data.as_slice());
+        assert_eq!(&index.decrypt_entry(entry).unwrap(), data);
+    }
+
+    // Stock XP/VX archives are recovered with the fixed key.
+    let archive_entries = [ArchiveEntry {
+        path: b"Data\\Scripts.rxdata",
+        data: b"\x04\x08[\x00",
+    }];
+    let options =
+        DecryptOptions::recover(&encrypt_with(&archive_entries, Engine::XP))
+            .unwrap();
+    assert_eq!(options.base_key, Some(0xDEAD_CAFE));
+
+    // Random data is never accepted.
+    let mut garbage = b"RGSSAD\0\x03".to_vec();
+    garbage.extend((0..=u8::MAX).cycle().skip(7).step_by(13).take(256));
+    assert!(matches!(
+        DecryptOptions::recover(&garbage),
+        Err(ExtractError::KeyNotFound)
+    ));
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn edit_archive_vxace() {
+    let archive_content = encrypt_vxace_fixture();
+    let mut archive = Archive::open(&archive_content).unwrap();
+    assert_eq!(archive.len(), 2);
+
+    let old_data = archive
+        .replace(b"Data\\Map001.rvdata2", b"\x04\x08[\x06i\x06".to_vec())
+        .unwrap();
+    assert_eq!(old_data.as_ref(), b"\x04\x08[\x00");
+    assert!(
+        archive
+            .replace(b"Data\\Map002.rvdata2", Vec::new())
+            .is_none()
+    );
+
+    assert!(
+        archive
+            .insert(b"Data\\System.rvdata2".as_slice(), b"\x04\x080".as_slice())
+            .is_none()
+    );
+    assert!(archive.remove(b"Graphics\\Pictures\\Title.png").is_some());
+    assert!(
+        archive.rename(
+            b"Data\\Map001.rvdata2",
+            b"Data\\Map002.rvdata2".as_slice()
+        )
+    );
+    assert!(
+        !archive.rename(
+            b"Data\\Map002.rvdata2",
+            b"Data\\System.rvdata2".as_slice()
+        )
+    );
+
+    // Path lookups follow removals and renames.
+    assert!(!archive.contains(b"Data\\Map001.rvdata2"));
+    assert!(!archive.contains(b"Graphics\\Pictures\\Title.png"));
+    assert_eq!(
+        archive.get(b"Data\\Map002.rvdata2").unwrap().data.as_ref(),
+        b"\x04\x08[\x06i\x06"
+    );
+    assert_eq!(
+        archive.get(b"Data\\System.rvdata2").unwrap().data.as_ref(),
+        b"\x04\x080"
+    );
+
+    let mut encrypted = archive.to_bytes(Engine::VXAce);
+    let decrypter = Decrypter::new();
+    let decrypted_files = decrypter
+        .decrypt(&mut encrypted)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(decrypted_files.len(), 2);
+    assert_eq!(decrypted_files[0].path, b"Data\\Map002.rvdata2");
+    assert_eq!(decrypted_files[0].data, b"\x04\x08[\x06i\x06");
+    assert_eq!(decrypted_files[1].path, b"Data\\System.rvdata2");
+    assert_eq!(decrypted_files[1].data, b"\x04\x080");
+
+    assert_eq!(Archive::from_entries(decrypted_files), archive);
+}
+
+fn encrypt_with(archive_entries: &[ArchiveEntry], engine: Engine) -> Vec<u8> {
+    let mut encrypted =
+        vec![0; Decrypter::encrypted_buffer_size(archive_entries, engine)];
+    Decrypter::new().encrypt(archive_entries, engine, &mut encrypted);
+    encrypted
+}
+
+#[cfg(feature = "alloc")]
+fn encrypt_with_options(
+    archive_entries: &[ArchiveEntry],
+    options: &mut EncryptOptions,
+) -> Vec<u8> {
+    let mut encrypted =
+        vec![
+            0;
+            Decrypter::encrypted_buffer_size(archive_entries, Engine::VXAce)
+        ];
+    Decrypter::new().encrypt_with_options(
+        archive_entries,
+        Engine::VXAce,
+        options,
         &mut encrypted,
     );
+    encrypted
+}
 
-    assert!(archive_content == encrypted);
+fn assert_round_trip(archive_entries: &[ArchiveEntry], engine: Engine) {
+    let mut encrypted = encrypt_with(archive_entries, engine);
+    let decrypter = Decrypter::new();
+    let decrypted_files = decrypter
+        .decrypt(&mut encrypted)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+
+    assert_eq!(decrypted_files.len(), archive_entries.len());
+
+    for (decrypted, original) in decrypted_files.iter().zip(archive_entries) {
+        assert_eq!(decrypted.path, original.path);
+        assert_eq!(decrypted.data, original.data);
+    }
+}
+
+#[test]
+fn encrypt_older_known_answer() {
+    let archive_entries = [ArchiveEntry {
+        path: b"a",
+        data: b"xyz12",
+    }];
+
+    assert_eq!(
+        encrypt_with(&archive_entries, Engine::XP),
+        [
+            0x52, 0x47, 0x53, 0x53, 0x41, 0x44, 0x00, 0x01, 0xFF, 0xCA, 0xAD,
+            0xDE, 0x94, 0xB3, 0xDA, 0x43, 0x9F, 0x85, 0x83, 0xA0, 0x6B, 0xDC,
+        ]
+    );
+}
+
+#[test]
+fn encrypt_older_round_trip() {
+    let large = (0..=u8::MAX).cycle().take(70_000).collect::<Vec<_>>();
+    let archive_entries = [
+        ArchiveEntry {
+            path: b"Data\\Scripts.rxdata",
+            data: b"\x04\x08[\x00",
+        },
+        ArchiveEntry {
+            path: b"Graphics\\Pictures\\Empty.png",
+            data: b"",
+        },
+        ArchiveEntry {
+            path: b"Audio\\BGM\\Theme.ogg",
+            data: &large,
+        },
+    ];
+
+    assert_round_trip(&archive_entries, Engine::XP);
+    assert_round_trip(&archive_entries, Engine::VXAce);
+}
+
+#[test]
+fn encrypt_reuses_decrypter() {
+    let archive_entries = [ArchiveEntry {
+        path: b"Data\\System.rvdata",
+        data: b"\x04\x080",
+    }];
+
+    for engine in [Engine::XP, Engine::VXAce] {
+        let size = Decrypter::encrypted_buffer_size(&archive_entries, engine);
+        let mut first = vec![0; size];
+        let mut second = vec![0; size];
+
+        let decrypter = Decrypter::new();
+        decrypter.encrypt(&archive_entries, engine, &mut first);
+        decrypter.encrypt(&archive_entries, engine, &mut second);
+
+        assert_eq!(first, second);
+    }
+}
+
+#[test]
+fn decrypt_reuses_decrypter() {
+    /// Decrypter can be stored alongside the archives, that it decrypted.
+    struct Session {
+        decrypter: Decrypter,
+        archives: Vec<Vec<u8>>,
+    }
+
+    let mut session = Session {
+        decrypter: Decrypter::new(),
+        archives: vec![
+            encrypt_vxace_fixture(),
+            encrypt_with(
+                &[ArchiveEntry {
+                    path: b"Data\\System.rxdata",
+                    data: b"\x04\x080",
+                }],
+                Engine::XP,
+            ),
+        ],
+    };
+
+    let decrypter = session.decrypter;
+    let mut entries = Vec::new();
+
+    // Entries of all archives are alive at once.
+    for archive_content in &mut session.archives {
+        for entry in decrypter.decrypt(archive_content).unwrap() {
+            entries.push(entry.unwrap());
+        }
+    }
+
+    let paths = entries.iter().map(|entry| entry.path).collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            b"Data\\Map001.rvdata2".as_slice(),
+            b"Graphics\\Pictures\\Title.png",
+            b"Data\\System.rxdata"
+        ]
+    );
+    assert_eq!(entries[2].data, b"\x04\x080");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn write_streaming() {
+    let large = (0..=u8::MAX).cycle().take(150_000).collect::<Vec<_>>();
+    let archive_entries = [
+        ArchiveEntry {
+            path: b"Data\\Scripts.rvdata2",
+            data: b"\x04\x08[\x00",
+        },
+        ArchiveEntry {
+            path: b"Graphics\\Pictures\\Empty.png",
+            data: b"",
+        },
+        ArchiveEntry {
+            path: b"Audio\\BGM\\Theme.ogg",
+            data: &large,
+        },
+    ];
+    let headers = archive_entries
+        .iter()
+        .map(|entry| EntryHeader {
+            path: entry.path,
+            size: entry.data.len() as u32,
+        })
+        .collect::<Vec<_>>();
+
+    for engine in [Engine::XP, Engine::VXAce] {
+        let mut writer =
+            ArchiveWriter::new(Vec::new(), engine, &headers).unwrap();
+
+        for entry in &archive_entries {
+            writer.write_entry(entry.data).unwrap();
+        }
+
+        let written = writer.finish().unwrap();
+        assert_eq!(written, encrypt_with(&archive_entries, engine));
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn write_streaming_size_mismatch() {
+    let headers = [
+        EntryHeader {
+            path: b"Data\\System.rvdata2",
+            size: 4,
+        },
+        EntryHeader {
+            path: b"Data\\Map001.rvdata2",
+            size: 4,
+        },
+    ];
+
+    for engine in [Engine::XP, Engine::VXAce] {
+        let mut writer =
+            ArchiveWriter::new(Vec::new(), engine, &headers).unwrap();
+
+        assert!(matches!(
+            writer.write_entry(&b"abc"[..]),
+            Err(WriteError::DataTooShort {
+                index: 0,
+                expected: 4,
+                actual: 3,
+            })
+        ));
+
+        // Partially written entry can't be completed or followed by other entries.
+        assert!(matches!(
+            writer.write_entry(&b"abcd"[..]),
+            Err(WriteError::Poisoned)
+        ));
+        assert!(matches!(writer.finish(), Err(WriteError::Poisoned)));
+
+        let mut writer =
+            ArchiveWriter::new(Vec::new(), engine, &headers).unwrap();
+
+        assert!(matches!(
+            writer.write_entry(&b"abcde"[..]),
+            Err(WriteError::DataTooLong {
+                index: 0,
+                expected: 4,
+            })
+        ));
+        assert!(matches!(
+            writer.write_entry(&b"abcd"[..]),
+            Err(WriteError::Poisoned)
+        ));
+
+        let mut writer =
+            ArchiveWriter::new(Vec::new(), engine, &headers).unwrap();
+        writer.write_entry(&b"abcd"[..]).unwrap();
+
+        assert!(matches!(
+            writer.finish(),
+            Err(WriteError::MissingEntries {
+                written: 1,
+                expected: 2,
+            })
+        ));
+
+        let mut writer =
+            ArchiveWriter::new(Vec::new(), engine, &headers[..1]).unwrap();
+        writer.write_entry(&b"abcd"[..]).unwrap();
+
+        assert!(matches!(
+            writer.write_entry(&b"abcd"[..]),
+            Err(WriteError::TooManyEntries(1))
+        ));
+
+        // Extra entry is rejected before anything is written.
+        assert_eq!(
+            writer.finish().unwrap(),
+            encrypt_with(
+                &[ArchiveEntry {
+                    path: headers[0].path,
+                    data: b"abcd",
+                }],
+                engine
+            )
+        );
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn encrypt_with_keys() {
+    let large = (0..=u8::MAX).cycle().take(70_000).collect::<Vec<_>>();
+    let archive_entries = [
+        ArchiveEntry {
+            path: b"Data\\Scripts.rvdata2",
+            data: b"\x04\x08[\x00",
+        },
+        ArchiveEntry {
+            path: b"Audio\\BGM\\Theme.ogg",
+            data: &large,
+        },
+        ArchiveEntry {
+            path: b"Graphics\\Pictures\\Empty.png",
+            data: b"",
+        },
+    ];
+    #[cfg(feature = "std")]
+    let headers = archive_entries
+        .iter()
+        .map(|entry| EntryHeader {
+            path: entry.path,
+            size: entry.data.len() as u32,
+        })
+        .collect::<Vec<_>>();
+
+    // Zero mode is the default.
+    let mut encrypted =
+        encrypt_with_options(&archive_entries, &mut EncryptOptions::default());
+    assert_eq!(encrypted, encrypt_with(&archive_entries, Engine::VXAce));
+
+    // Deterministic xorshift generator
+    let xorshift = |mut state: u32| {
+        move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        }
+    };
+
+    for make_options in [
+        (|_| EncryptOptions {
+            base_key: 0xCAFE_BABE,
+            entry_keys: EntryKeys::MetadataKey,
+        }) as fn(&mut dyn FnMut() -> u32) -> EncryptOptions<'_>,
+        |_| EncryptOptions {
+            base_key: 0x1234_5678,
+            entry_keys: EntryKeys::Fixed(0x9ABC_DEF0),
+        },
+        |_| EncryptOptions {
+            base_key: 0x1234_5678,
+            entry_keys: EntryKeys::Derived,
+        },
+        |rng| EncryptOptions::random(rng),
+    ] {
+        let mut rng = xorshift(0x2545_F491);
+        encrypted =
+            encrypt_with_options(&archive_entries, &mut make_options(&mut rng));
+        assert_ne!(encrypted, encrypt_with(&archive_entries, Engine::VXAce));
+
+        let index = ArchiveIndex::new(&encrypted).unwrap();
+        assert_eq!(index.entries().len(), archive_entries.len());
+
+        for (entry, archive_entry) in
+            index.entries().iter().zip(&archive_entries)
+        {
+            assert_eq!(entry.path, archive_entry.path);
+            assert_eq!(index.decrypt_entry(entry).unwrap(), archive_entry.data);
+        }
+
+        // Streaming writer produces the same archive with the same keys.
+        #[cfg(feature = "std")]
+        {
+            let mut rng = xorshift(0x2545_F491);
+            let mut writer = ArchiveWriter::with_options(
+                Vec::new(),
+                Engine::VXAce,
+                &headers,
+                &mut make_options(&mut rng),
+            )
+            .unwrap();
+
+            for archive_entry in &archive_entries {
+                writer.write_entry(archive_entry.data).unwrap();
+            }
+
+            assert_eq!(writer.finish().unwrap(), encrypted);
+        }
+    }
+
+    // Entry keys are actually different with derived and random keys.
+    for mut options in [
+        EncryptOptions {
+            base_key: 0,
+            entry_keys: EntryKeys::Derived,
+        },
+        EncryptOptions::random(&mut xorshift(1)),
+    ] {
+        let encrypted = encrypt_with_options(&archive_entries, &mut options);
+        let index = ArchiveIndex::new(&encrypted).unwrap();
+        assert_ne!(index.entries()[0].key, index.entries()[1].key);
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn preserve_layout() {
+    let archive_entries = [
+        ArchiveEntry {
+            path: b"Data\\Scripts.rvdata2",
+            data: b"\x04\x08[\x00",
+        },
+        ArchiveEntry {
+            path: b"Audio\\BGM\\Theme.ogg",
+            data: b"OggS\x00\x02",
+        },
+        ArchiveEntry {
+            path: b"Graphics\\Pictures\\Empty.png",
+            data: b"",
+        },
+    ];
+
+    let mut state = 0x2545_F491u32;
+    let mut rng = || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+
+    let mut vxace = encrypt_with_options(
+        &archive_entries,
+        &mut EncryptOptions::random(&mut rng),
+    );
+
+    // RPG Maker leaves garbage after the terminator offset.
+    let trailer_start = 8
+        + 4
+        + archive_entries
+            .iter()
+            .map(|entry| 16 + entry.path.len())
+            .sum::<usize>()
+        + 4;
+    vxace[trailer_start..trailer_start + 12]
+        .copy_from_slice(b"garbage\xFF\x00\x01\x02\x03");
+
+    for (engine, encrypted) in [
+        (Engine::VXAce, vxace),
+        (Engine::XP, encrypt_with(&archive_entries, Engine::XP)),
+    ] {
+        let layout = ArchiveLayout::new(&encrypted).unwrap();
+        assert_eq!(layout.engine, engine);
+        assert_eq!(layout.entries.len(), archive_entries.len());
+
+        // Order of the archive files doesn't matter.
+        let mut archive = Archive::open(&encrypted).unwrap();
+        let theme = archive.remove(b"Audio\\BGM\\Theme.ogg").unwrap();
+        archive.insert(theme.path, theme.data);
+
+        assert_eq!(archive.to_bytes_with_layout(&layout), encrypted);
+
+        archive
+            .replace(b"Data\\Scripts.rvdata2", b"\x04\x08[\x06i\x06".to_vec());
+        archive.remove(b"Graphics\\Pictures\\Empty.png");
+        archive.insert(
+            b"Data\\System.rvdata2".as_slice(),
+            b"\x04\x080".as_slice(),
+        );
+
+        let modified = archive.to_bytes_with_layout(&layout);
+        assert_eq!(modified[..12], encrypted[..12]);
+
+        let index = ArchiveIndex::new(&modified).unwrap();
+        let paths = index
+            .entries()
+            .iter()
+            .map(|entry| entry.path.as_slice())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                b"Data\\Scripts.rvdata2".as_slice(),
+                b"Audio\\BGM\\Theme.ogg",
+                b"Data\\System.rvdata2",
+            ]
+        );
+
+        for entry in index.entries() {
+            assert_eq!(
+                index.decrypt_entry(entry).unwrap(),
+                archive.get(&entry.path).unwrap().data.as_ref()
+            );
+        }
+
+        if engine.is_vx_ace() {
+            let modified_layout = ArchiveLayout::new(&modified).unwrap();
+            assert_eq!(modified_layout.trailer, layout.trailer);
+            assert_eq!(
+                modified_layout.entries[1].stored_key,
+                layout.entries[1].stored_key
+            );
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn preserve_layout_duplicate_paths() {
+    let archive_entries = [
+        ArchiveEntry {
+            path: b"Data\\Map001.rvdata2",
+            data: b"\x04\x08[\x00",
+        },
+        ArchiveEntry {
+            path: b"Audio\\BGM\\Theme.ogg",
+            data: b"OggS\x00\x02",
+        },
+        ArchiveEntry {
+            path: b"Data\\Map001.rvdata2",
+            data: b"\x04\x08[\x06i\x06",
+        },
+    ];
+
+    for engine in [Engine::XP, Engine::VXAce] {
+        let encrypted = encrypt_with(&archive_entries, engine);
+        let layout = ArchiveLayout::new(&encrypted).unwrap();
+        assert_eq!(layout.entries.len(), 3);
+
+        let archive = Archive::open(&encrypted).unwrap();
+        assert_eq!(archive.len(), 2);
+
+        let rewritten = archive.to_bytes_with_layout(&layout);
+        let index = ArchiveIndex::new(&rewritten).unwrap();
+        let paths = index
+            .entries()
+            .iter()
+            .map(|entry| entry.path.as_slice())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [b"Data\\Map001.rvdata2".as_slice(), b"Audio\\BGM\\Theme.ogg"]
+        );
+
+        // The last duplicate wins when opening.
+        assert_eq!(
+            index.decrypt_entry(&index.entries()[0]).unwrap(),
+            b"\x04\x08[\x06i\x06"
+        );
+    }
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn patch_archives() {
+    let map = (0..20_000u32)
+        .flat_map(|idx| format!("Text {idx};").into_bytes())
+        .collect::<Vec<_>>();
+    let mut translated_map = map.clone();
+    translated_map.splice(1000..1010, b"Translated text".iter().copied());
+    translated_map.splice(60_000..60_004, []);
+
+    let source_content = encrypt_with(
+        &[
+            ArchiveEntry {
+                path: b"Data\\Map001.rvdata2",
+                data: &map,
+            },
+            ArchiveEntry {
+                path: b"Data\\System.rvdata2",
+                data: b"\x04\x080",
+            },
+            ArchiveEntry {
+                path: b"Graphics\\Pictures\\Title.png",
+                data: b"\x89PNG\r\n\x1a\n",
+            },
+        ],
+        Engine::VXAce,
+    );
+
+    let source = Archive::open(&source_content).unwrap();
+    let mut target = source.clone();
+    target.replace(b"Data\\Map001.rvdata2", translated_map.as_slice());
+    target.remove(b"Graphics\\Pictures\\Title.png");
+    target.insert(
+        b"Graphics\\Pictures\\Title_en.png".as_slice(),
+        b"\x89PNG\r\n\x1a\n\x00".as_slice(),
+    );
+
+    let patch = Patch::diff(&source, &target);
+    assert_eq!(patch.source_hash, source.content_hash());
+    assert_eq!(patch.target_hash, target.content_hash());
+    assert!(matches!(
+        patch.operations.as_slice(),
+        [
+            PatchOperation::Remove { .. },
+            PatchOperation::Change { .. },
+            PatchOperation::Add { .. }
+        ]
+    ));
+
+    // Only the changed bytes of the large file are stored.
+    let patch_data = patch.to_bytes();
+    assert!(patch_data.len() < 256);
+    assert_eq!(Patch::from_bytes(&patch_data).unwrap(), patch);
+
+    // Hash doesn't depend on the keys and order of files.
+    let mut patched = Archive::open(&source_content).unwrap();
+    let system = patched.remove(b"Data\\System.rvdata2").unwrap();
+    patched.insert(system.path, system.data);
+
+    let mut rng = || 0x1234_5678;
+    let rekeyed = patched.to_bytes_with_options(
+        Engine::VXAce,
+        &mut EncryptOptions::random(&mut rng),
+    );
+    let mut patched = Archive::open(&rekeyed).unwrap();
+
+    patch.apply(&mut patched).unwrap();
+    assert_eq!(patched.content_hash(), target.content_hash());
+    assert_eq!(
+        patched.get(b"Data\\Map001.rvdata2").unwrap().data.as_ref(),
+        translated_map
+    );
+
+    // Patch can't be applied twice.
+    assert!(matches!(
+        patch.apply(&mut patched),
+        Err(PatchError::SourceMismatch { .. })
+    ));
+
+    // Corrupted patches are rejected without modifying the archive.
+    let mut archive = source.clone();
+    let mut corrupted = patch.clone();
+
+    for operation in &mut corrupted.operations {
+        if let PatchOperation::Change { delta, .. } = operation {
+            delta.truncate(delta.len() - 1);
+        }
+    }
+
+    assert!(matches!(
+        corrupted.apply(&mut archive),
+        Err(PatchError::InvalidDelta { path }) if path == b"Data\\Map001.rvdata2"
+    ));
+    assert_eq!(archive, source);
+
+    // Deltas, that describe more data than their target size, are rejected before allocating it.
+    for delta in [
+        // Target size of 1 TiB, produced by copying the whole source over and over
+        [
+            &[0x80, 0x80, 0x80, 0x80, 0x80, 0x20][..],
+            &[0, 0, 0x80, 0x80, 0x01],
+        ]
+        .concat(),
+        // Copy, that exceeds the target size of 4 bytes
+        vec![4, 0, 0, 8],
+    ] {
+        let mut corrupted = patch.clone();
+
+        for operation in &mut corrupted.operations {
+            if let PatchOperation::Change {
+                delta: old_delta, ..
+            } = operation
+            {
+                old_delta.clone_from(&delta);
+            }
+        }
+
+        assert!(matches!(
+            corrupted.apply(&mut archive),
+            Err(PatchError::InvalidDelta { .. })
+        ));
+        assert_eq!(archive, source);
+    }
+
+    corrupted = patch.clone();
+    corrupted.target_hash ^= 1;
+    assert!(matches!(
+        corrupted.apply(&mut archive),
+        Err(PatchError::TargetMismatch { .. })
+    ));
+    assert_eq!(archive, source);
+
+    assert!(matches!(
+        Patch::from_bytes(&patch_data[..patch_data.len() - 1]),
+        Err(PatchError::UnexpectedEof(_))
+    ));
+    assert!(matches!(
+        Patch::from_bytes(b"RGSSPTCX\x01"),
+        Err(PatchError::InvalidHeader(_))
+    ));
+    assert!(matches!(
+        Patch::from_bytes(b"RGSSPTCH\x02"),
+        Err(PatchError::UnsupportedVersion(2))
+    ));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn sanitize_entry_paths() {
+    let sanitize = |path: &[u8]| sanitize_entry_path(path, PathEncoding::Utf8);
+
+    assert_eq!(
+        sanitize(b"Data\\Map001.rvdata2").unwrap(),
+        PathBuf::from("Data").join("Map001.rvdata2")
+    );
+    assert_eq!(
+        sanitize(b".\\Graphics//Pictures\\Title.png").unwrap(),
+        PathBuf::from("Graphics").join("Pictures").join("Title.png")
+    );
+    assert!(matches!(
+        sanitize(b"..\\..\\Windows\\evil.dll"),
+        Err(ExtractFileError::PathTraversal(_))
+    ));
+    assert!(matches!(
+        sanitize(b"Data\\..\\..\\evil.dll"),
+        Err(ExtractFileError::PathTraversal(_))
+    ));
+    assert!(matches!(
+        sanitize(b"C:\\Windows\\evil.dll"),
+        Err(ExtractFileError::AbsolutePath(_))
+    ));
+    assert!(matches!(
+        sanitize(b"\\\\server\\share\\evil.dll"),
+        Err(ExtractFileError::AbsolutePath(_))
+    ));
+    assert!(matches!(
+        sanitize(b"/etc/passwd"),
+        Err(ExtractFileError::AbsolutePath(_))
+    ));
+    assert!(matches!(
+        sanitize(b".\\.\\"),
+        Err(ExtractFileError::EmptyPath)
+    ));
+    assert!(matches!(
+        sanitize(b"Data\\C:evil.dll"),
+        Err(ExtractFileError::InvalidComponent(_))
+    ));
+    assert!(matches!(
+        sanitize(b"Data/C:\\Windows\\evil.dll"),
+        Err(ExtractFileError::InvalidComponent(_))
+    ));
+    assert!(matches!(
+        sanitize(b"Data\\Map001.rvdata2:evil"),
+        Err(ExtractFileError::InvalidComponent(_))
+    ));
+    assert!(matches!(
+        sanitize(b"Data\\Map001.rvdata2."),
+        Err(ExtractFileError::InvalidComponent(_))
+    ));
+    assert!(matches!(
+        sanitize(b"Data \\Map001.rvdata2"),
+        Err(ExtractFileError::InvalidComponent(_))
+    ));
+    assert!(matches!(
+        sanitize(b"Data\\nul.txt"),
+        Err(ExtractFileError::InvalidComponent(_))
+    ));
+    assert!(matches!(
+        sanitize(b"COM1\\Map001.rvdata2"),
+        Err(ExtractFileError::InvalidComponent(_))
+    ));
+    assert_eq!(
+        sanitize(b"Data\\Console.rvdata2").unwrap(),
+        PathBuf::from("Data").join("Console.rvdata2")
+    );
+
+    assert!(matches!(sanitize(b""), Err(ExtractFileError::EmptyPath)));
+
+    // Shift JIS "マップ"
+    assert!(matches!(
+        sanitize(b"Data\\\x83}\x83b\x83v.rvdata2"),
+        Err(ExtractFileError::InvalidEncoding(PathEncoding::Utf8))
+    ));
+    assert!(
+        sanitize_entry_path(
+            b"Data\\\x83}\x83b\x83v.rvdata2",
+            PathEncoding::Utf8Lossy
+        )
+        .is_ok()
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn extract_to_directory() {
+    let archive_entries = [
+        ArchiveEntry {
+            path: b"Data\\Map001.rvdata2",
+            data: b"\x04\x08[\x00",
+        },
+        ArchiveEntry {
+            path: b"..\\evil.txt",
+            data: b"evil",
+        },
+        ArchiveEntry {
+            path: b"C:\\evil.txt",
+            data: b"evil",
+        },
+    ];
+
+    let archive_content = encrypt_with(&archive_entries, Engine::VXAce);
+    let index = ArchiveIndex::new(&archive_content).unwrap();
+
+    let output_dir = temp_dir()
+        .join(format!("rpgmad-extract-{}", std::process::id()))
+        .join("Game");
+    let _ = remove_dir_all(&output_dir);
+
+    let extracted = index.extract_to(&output_dir, &ExtractOptions::default());
+    assert_eq!(extracted.len(), 3);
+
+    let map_path = extracted[0].result.as_ref().unwrap();
+    assert_eq!(*map_path, output_dir.join("Data").join("Map001.rvdata2"));
+    assert_eq!(read(map_path).unwrap(), b"\x04\x08[\x00");
+
+    assert!(matches!(
+        extracted[1].result,
+        Err(ExtractFileError::PathTraversal(_))
+    ));
+    assert!(matches!(
+        extracted[2].result,
+        Err(ExtractFileError::AbsolutePath(_))
+    ));
+    assert!(!output_dir.parent().unwrap().join("evil.txt").exists());
+
+    let options = ExtractOptions {
+        overwrite: false,
+        ..Default::default()
+    };
+    let extracted = index.extract_to(&output_dir, &options);
+    assert!(matches!(
+        extracted[0].result,
+        Err(ExtractFileError::AlreadyExists(_))
+    ));
+
+    remove_dir_all(output_dir.parent().unwrap()).unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn pack_game_directory() {
+    let game_dir =
+        temp_dir().join(format!("rpgmad-pack-{}", std::process::id()));
+    let _ = remove_dir_all(&game_dir);
+
+    for (path, data) in [
+        ("Game.exe", b"MZ".as_slice()),
+        ("Game.ini", b"[Game]"),
+        ("Game.rgss3a", b"RGSSAD\0\x03"),
+        ("Data/System.rvdata2", b"\x04\x080"),
+        ("Data/Map001.rvdata2", b"\x04\x08[\x00"),
+        ("Graphics/Pictures/Title.png", b"\x89PNG\r\n\x1a\n"),
+        ("Graphics/Pictures/Title.psd", b"8BPS"),
+        ("Audio/BGM/Theme.ogg", b"OggS"),
+    ] {
+        let path = game_dir.join(path);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, data).unwrap();
+    }
+
+    // Links to the ancestor directories are not followed.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::symlink;
+        symlink(&game_dir, game_dir.join("Graphics/Game")).unwrap();
+        symlink("..", game_dir.join("Graphics/Pictures/Graphics")).unwrap();
+    }
+
+    let packed_paths = |options: &PackOptions| {
+        let mut archive = pack_dir(&game_dir, Engine::VXAce, options).unwrap();
+        let decrypter = Decrypter::new();
+        let paths = decrypter
+            .decrypt(&mut archive)
+            .unwrap()
+            .map(|entry| {
+                String::from_utf8(entry.unwrap().path.to_vec()).unwrap()
+            })
+            .collect::<Vec<_>>();
+        paths
+    };
+
+    assert_eq!(
+        packed_paths(&PackOptions::default()),
+        [
+            "Data\\Map001.rvdata2",
+            "Data\\System.rvdata2",
+            "Graphics\\Pictures\\Title.png",
+            "Graphics\\Pictures\\Title.psd",
+        ]
+    );
+
+    let options = PackOptions {
+        include: vec!["**".into()],
+        exclude: vec!["**/*.PSD".into(), "data/system.*".into()],
+        include_audio: true,
+        skip_game_files: true,
+        encoding: PathEncoding::Utf8,
+    };
+
+    assert_eq!(
+        packed_paths(&options),
+        [
+            "Audio\\BGM\\Theme.ogg",
+            "Data\\Map001.rvdata2",
+            "Graphics\\Pictures\\Title.png",
+        ]
+    );
+
+    let options = PackOptions {
+        include: vec!["*".into()],
+        skip_game_files: false,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        packed_paths(&options),
+        ["Game.exe", "Game.ini", "Game.rgss3a"]
+    );
+
+    // Packing is deterministic
+    assert_eq!(
+        pack_dir(&game_dir, Engine::XP, &PackOptions::default()).unwrap(),
+        pack_dir(&game_dir, Engine::XP, &PackOptions::default()).unwrap()
+    );
+
+    // Names, that aren't valid UTF-8, are only rejected, if they could be packed.
+    #[cfg(unix)]
+    {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        create_dir_all(game_dir.join(OsStr::from_bytes(b"Movies\xFF")))
+            .unwrap();
+        write(game_dir.join(OsStr::from_bytes(b"Save\xFF.rvdata2")), b"")
+            .unwrap();
+        assert_eq!(packed_paths(&PackOptions::default()).len(), 4);
+
+        let invalid_path =
+            game_dir.join(OsStr::from_bytes(b"Data/Map\xFF.rvdata2"));
+        write(&invalid_path, b"").unwrap();
+        assert!(matches!(
+            pack_dir(&game_dir, Engine::VXAce, &PackOptions::default()),
+            Err(PackError::InvalidFileName(path)) if path == invalid_path
+        ));
+    }
+
+    remove_dir_all(game_dir).unwrap();
+}
+
+#[cfg(feature = "encoding")]
+#[test]
+fn cp932_paths() {
+    // Shift JIS "Data\マップ.rvdata2"
+    let cp932_path = b"Data\\\x83}\x83b\x83v.rvdata2";
+    let entry = ArchiveEntry {
+        path: cp932_path,
+        data: b"",
+    };
+
+    assert_eq!(PathEncoding::detect(cp932_path), Some(PathEncoding::Cp932));
+    assert_eq!(
+        PathEncoding::detect("Data\\マップ.rvdata2".as_bytes()),
+        Some(PathEncoding::Utf8)
+    );
+    assert!(entry.path_str(PathEncoding::Utf8).is_none());
+    assert_eq!(
+        entry.path_str(PathEncoding::Cp932).unwrap(),
+        "Data\\マップ.rvdata2"
+    );
+    assert_eq!(entry.decode_path(), "Data\\マップ.rvdata2");
+
+    let encoded = PathEncoding::Cp932.encode("Data\\マップ.rvdata2").unwrap();
+    assert_eq!(encoded.as_ref(), cp932_path);
+
+    assert!(PathEncoding::Cp932.encode("Data\\🗺.rvdata2").is_none());
+    assert_eq!(
+        PathEncoding::Auto
+            .encode("Data\\🗺.rvdata2")
+            .unwrap()
+            .as_ref(),
+        "Data\\🗺.rvdata2".as_bytes()
+    );
+}
+
+#[cfg(all(feature = "std", feature = "encoding"))]
+#[test]
+fn cp932_pack_and_extract() {
+    let base_dir =
+        temp_dir().join(format!("rpgmad-cp932-{}", std::process::id()));
+    let _ = remove_dir_all(&base_dir);
+
+    let game_dir = base_dir.join("Game");
+    create_dir_all(game_dir.join("Data")).unwrap();
+    write(game_dir.join("Data").join("マップ.rvdata2"), b"\x04\x080").unwrap();
+
+    let options = PackOptions {
+        encoding: PathEncoding::Cp932,
+        ..Default::default()
+    };
+    let archive_content = pack_dir(&game_dir, Engine::VXAce, &options).unwrap();
+    let index = ArchiveIndex::new(&archive_content).unwrap();
+    assert_eq!(index.entries()[0].path, b"Data\\\x83}\x83b\x83v.rvdata2");
+
+    let options = ExtractOptions {
+        encoding: PathEncoding::Auto,
+        ..Default::default()
+    };
+    let output_dir = base_dir.join("Output");
+    let extracted = index.extract_to(&output_dir, &options);
+    assert_eq!(
+        *extracted[0].result.as_ref().unwrap(),
+        output_dir.join("Data").join("マップ.rvdata2")
+    );
+
+    remove_dir_all(base_dir).unwrap();
+}
+
+#[test]
+fn mv_asset_round_trip() {
+    let key = AssetKey::from_hex("d41d8cd98f00b204e9800998ecf8427e").unwrap();
+    let decrypter = AssetDecrypter::new(key);
+
+    let mut png = vec![
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D,
+        0x49, 0x48, 0x44, 0x52,
+    ];
+    png.extend_from_slice(b"\x00\x00\x00\x10\x00\x00\x00\x10rest of the image");
+
+    let mut encrypted = vec![0; AssetDecrypter::encrypted_buffer_size(&png)];
+    decrypter.encrypt(&png, &mut encrypted).unwrap();
+
+    assert_eq!(encrypted[..ASSET_HEADER_SIZE], ASSET_HEADER);
+    assert_ne!(encrypted[ASSET_HEADER_SIZE..32], png[..16]);
+    assert_eq!(encrypted[32..], png[16..]);
+
+    // Key is recovered from any encrypted PNG.
+    assert_eq!(AssetKey::recover_from_png(&encrypted).unwrap(), key);
+
+    assert_eq!(decrypter.decrypt(&mut encrypted).unwrap(), png);
+
+    // Files shorter than the key are encrypted too.
+    let mut short = vec![0; AssetDecrypter::encrypted_buffer_size(b"ogg")];
+    decrypter.encrypt(b"ogg", &mut short).unwrap();
+    assert_eq!(decrypter.decrypt(&mut short).unwrap(), b"ogg");
+}
+
+#[test]
+fn mv_asset_errors() {
+    assert!(matches!(
+        AssetKey::from_hex("d41d8cd9"),
+        Err(AssetError::InvalidKeyLength(8))
+    ));
+    assert!(matches!(
+        AssetKey::from_hex("z41d8cd98f00b204e9800998ecf8427e"),
+        Err(AssetError::InvalidKeyChar('z'))
+    ));
+
+    let decrypter = AssetDecrypter::new(AssetKey::new([0xAA; 16]));
+
+    let mut not_encrypted =
+        b"OggS\x00\x02\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+    assert!(matches!(
+        decrypter.decrypt(&mut not_encrypted),
+        Err(AssetError::InvalidHeader(_))
+    ));
+
+    assert!(matches!(
+        decrypter.decrypt(&mut b"RPGMV".to_vec()),
+        Err(AssetError::UnexpectedEof {
+            size: 5,
+            needed: 16
+        })
+    ));
+    assert!(matches!(
+        AssetKey::recover_from_png(&ASSET_HEADER),
+        Err(AssetError::UnexpectedEof {
+            size: 16,
+            needed: 32
+        })
+    ));
+
+    assert!(matches!(
+        decrypter.encrypt(b"png", &mut [0; 16]),
+        Err(AssetError::BufferSizeMismatch {
+            expected: 19,
+            actual: 16
+        })
+    ));
+
+    assert_eq!(asset_extension("rpgmvp"), Some("png"));
+    assert_eq!(asset_extension("OGG_"), Some("ogg"));
+    assert_eq!(asset_extension("rpgmvm"), Some("m4a"));
+    assert_eq!(asset_extension("png"), None);
 }